@@ -2,16 +2,21 @@ pub mod definitions;
 pub mod player;
 pub mod world;
 
-use std::time::Instant;
+use std::{
+    fs,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-use cgmath::{Vector3, Vector4, Zero};
+use cgmath::{Vector3, Zero};
 use common::{
+    assets::{AssetManager, FontHandle, TextureHandle},
     buffer2d::{
-        text::Font,
+        sampler::Sampler,
         virtual_window::{VirtualWindowStack, WindowBorder},
-        B2DO, B2DS,
+        B2DS,
     },
     console::Console,
+    image::bmp,
     platform::{
         input::{Input, InputCode},
         Application,
@@ -22,6 +27,8 @@ use common::{
 
 use self::{definitions::VW_TEST_A, player::Player, world::World};
 
+const SCREENSHOT_DIRECTORY: &str = "./screenshots";
+
 pub enum GameState {
     Action,
     Automap,
@@ -35,10 +42,11 @@ pub struct Game {
     pub camera: Camera,
     pub player: Player,
     pub world: World,
-    pub texture: B2DO,
-    pub crusader: B2DO,
+    pub assets: AssetManager,
+    pub world_textures: Vec<TextureHandle>,
+    pub sprite_textures: Vec<TextureHandle>,
     pub border: WindowBorder,
-    pub font: Font,
+    pub font: FontHandle,
     pub x: i32,
     pub y: i32,
     pub tick: f32,
@@ -91,10 +99,22 @@ impl Application for Game {
             println!("{:?}", dt);
         }
 
+        if input.is_pressed(InputCode::F7) {
+            self.toggle_indexed();
+        }
+
+        if input.is_pressed(InputCode::F8) {
+            self.renderer.dither = self.renderer.dither.next();
+            self.assets.set_dither(self.renderer.dither);
+            self.console
+                .put_string(format!("Dither: {:?}", self.renderer.dither));
+        }
+
         if input.is_pressed(InputCode::F10) {
             self.renderer.debug_mode = match self.renderer.debug_mode {
                 RenderDebugMode::None => RenderDebugMode::ZBuffer,
-                RenderDebugMode::ZBuffer => RenderDebugMode::None,
+                RenderDebugMode::ZBuffer => RenderDebugMode::MipLevels,
+                RenderDebugMode::MipLevels => RenderDebugMode::None,
                 RenderDebugMode::Clickables => RenderDebugMode::None,
             }
         }
@@ -110,6 +130,9 @@ impl Application for Game {
             // ));
         }
 
+        self.assets.update(dt);
+        self.assets.report(&mut self.console);
+
         if !self.console.update(dt, input) {
             self.stack.update(input);
             self.player.update(dt, input);
@@ -119,29 +142,43 @@ impl Application for Game {
         // test_a.minimized = !input.is_held(InputCode::LMB);
 
         if let Some(mut main_buffer) = main_buffer {
-            main_buffer.bitmap.fill(color_from_tuple((2, 2, 2)));
+            main_buffer.fill(color_from_tuple((2, 2, 2)));
 
             self.renderer.begin(self.camera.proj, self.player.view);
 
-            draw_grid(&mut self.renderer, Vector3::<f32>::zero(), 0.5);
-
             match self.game_state {
                 GameState::Action => {
-                    for (v0, v1, v2) in &self.world.triangles {
-                        self.renderer.draw_triangle(v0, v1, v2, Some(&self.texture));
+                    for (surface, texture) in self.world.surfaces.iter().zip(&self.world_textures) {
+                        for (v0, v1, v2) in &self.world.triangles[surface.triangles.clone()] {
+                            self.renderer.draw_triangle(
+                                v0,
+                                v1,
+                                v2,
+                                Some(*texture),
+                                surface.sampler,
+                                &self.assets,
+                            );
+                        }
                     }
 
-                    for i in 0..10 {
+                    for (sprite, texture) in self.world.sprites.iter().zip(&self.sprite_textures) {
                         self.renderer.draw_sprite(
-                            Vector4::new(0.25 + (i as f32 * 0.1), 0.0, 1.5 + (i as f32 * 0.4), 1.0),
-                            1.0,
-                            &self.crusader,
+                            sprite.pos,
+                            sprite.size,
+                            *texture,
+                            Sampler::CLAMP,
+                            &self.assets,
                         );
                     }
                 }
                 GameState::Automap => {}
             }
 
+            // Depth tested, so it has to come after the geometry it hides behind
+            draw_grid(&mut self.renderer, Vector3::<f32>::zero(), 0.5);
+
+            self.renderer.end();
+
             // buffer.blit_buffer(&self.renderer.color_buffer, 0, 0);
 
             // if !test_a.minimized {
@@ -166,8 +203,81 @@ impl Application for Game {
             // main_buffer.blit_buffer_full_masked(&self.crusader, (0, 0));
             self.stack.blit(&self.border, &mut main_buffer);
             self.console.blit(&mut main_buffer);
+
+            if input.is_pressed(InputCode::Snapshot) || input.is_pressed(InputCode::F9) {
+                self.save_screenshots(&main_buffer);
+            }
         }
 
         return true;
     }
 }
+
+impl Game {
+    fn toggle_indexed(&mut self) {
+        if self.renderer.indexed.take().is_some() {
+            self.assets.set_palette(None);
+            self.console.put_line("Indexed rendering off");
+            return;
+        }
+
+        let indexed = match self.assets.read_indexed_palette() {
+            Ok(indexed) => indexed,
+            Err(error) => {
+                self.console
+                    .put_error(&format!("Failed to read the palette: {}", error));
+                return;
+            }
+        };
+
+        match indexed {
+            Some(indexed) => {
+                self.assets.set_palette(Some(indexed.base()));
+                self.renderer.indexed = Some(indexed);
+                self.console.put_line("Indexed rendering on");
+            }
+            None => self
+                .console
+                .put_warning("No PLAYPAL found for indexed rendering"),
+        }
+    }
+
+    fn save_screenshots(&mut self, main_buffer: &B2DS) {
+        if let Err(error) = fs::create_dir_all(SCREENSHOT_DIRECTORY) {
+            self.console.put_error(&format!(
+                "Failed to create \"{}\": {}",
+                SCREENSHOT_DIRECTORY, error
+            ));
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        let mut paths = vec![];
+
+        let path = format!("{}/{}_main.bmp", SCREENSHOT_DIRECTORY, timestamp);
+        paths.push((path.clone(), bmp::save_bmp(main_buffer, &path)));
+
+        for window in &self.stack.windows {
+            let path = format!(
+                "{}/{}_{}.bmp",
+                SCREENSHOT_DIRECTORY,
+                timestamp,
+                window.name.to_lowercase().replace(' ', "_")
+            );
+            paths.push((path.clone(), bmp::save_bmp(&window.buffer.borrow(), &path)));
+        }
+
+        for (path, result) in paths {
+            match result {
+                Ok(_) => self.console.put_string(format!("Saved \"{}\"", path)),
+                Err(error) => self
+                    .console
+                    .put_error(&format!("Failed to save \"{}\": {}", path, error)),
+            }
+        }
+    }
+}
@@ -1,10 +1,88 @@
-use cgmath::{Vector2, Vector3, Vector4};
-use common::renderer::Vertex;
+use std::{f32::consts::FRAC_PI_2, ops::Range};
+
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use common::{
+    buffer2d::sampler::Sampler,
+    renderer::Vertex,
+    wad::map::{Map, Sector, Sidedef, NO_TEXTURE, SKY_FLAT},
+};
 
 const MAP_SCALE: f32 = 0.01;
 
+const THING_SIZE: f32 = 56.0;
+const PLAYER_EYE_HEIGHT: f32 = 41.0;
+const PLAYER_START: u16 = 1;
+
+// Drawn by the test world, whose placements don't come from things
+const TEST_SPRITE: &str = "crusader";
+
+// Thing kinds and the first frame of their sprite, monsters face the camera
+// Kinds missing here, like player starts and teleport destinations, are not drawn
+const THING_SPRITES: [(u16, &str); 36] = [
+    (3004, "POSSA1"),
+    (9, "SPOSA1"),
+    (3001, "TROOA1"),
+    (3002, "SARGA1"),
+    (58, "SARGA1"),
+    (3006, "SKULA1"),
+    (3005, "HEADA1"),
+    (3003, "BOSSA1"),
+    (16, "CYBRA1"),
+    (7, "SPIDA1"),
+    (2001, "SHOTA0"),
+    (2002, "MGUNA0"),
+    (2003, "LAUNA0"),
+    (2004, "PLASA0"),
+    (2005, "CSAWA0"),
+    (2006, "BFUGA0"),
+    (2007, "CLIPA0"),
+    (2008, "SHELA0"),
+    (2010, "ROCKA0"),
+    (2048, "AMMOA0"),
+    (2049, "SBOXA0"),
+    (8, "BPAKA0"),
+    (2011, "STIMA0"),
+    (2012, "MEDIA0"),
+    (2014, "BON1A0"),
+    (2015, "BON2A0"),
+    (2018, "ARM1A0"),
+    (2019, "ARM2A0"),
+    (2013, "SOULA0"),
+    (5, "BKEYA0"),
+    (6, "YKEYA0"),
+    (13, "RKEYA0"),
+    (2035, "BAR1A0"),
+    (2028, "COLUA0"),
+    (34, "CANDA0"),
+    (35, "CBRAA0"),
+];
+
+// A run of triangles sharing one texture
+pub struct Surface {
+    pub texture: String,
+    pub triangles: Range<usize>,
+    pub sampler: Sampler,
+    // Map surfaces are built in texels since texture sizes are only known after loading
+    pub texel_uvs: bool,
+}
+
+pub struct SpritePlacement {
+    pub pos: Vector4<f32>,
+    pub size: f32,
+    pub kind: u16,
+}
+
+impl SpritePlacement {
+    pub fn sprite(&self) -> &'static str {
+        thing_sprite(self.kind).unwrap_or(TEST_SPRITE)
+    }
+}
+
 pub struct World {
     pub triangles: Vec<(Vertex, Vertex, Vertex)>,
+    pub surfaces: Vec<Surface>,
+    pub sprites: Vec<SpritePlacement>,
+    pub player_start: Option<(Vector3<f32>, f32)>,
 }
 
 impl World {
@@ -96,8 +174,477 @@ impl World {
             }
         }
 
+        let sprites = Vec::from_iter((0..10).map(|i| SpritePlacement {
+            pos: Vector4::new(0.25 + (i as f32 * 0.1), 0.0, 1.5 + (i as f32 * 0.4), 1.0),
+            size: 1.0,
+            kind: 0,
+        }));
+
+        let surfaces = vec![Surface {
+            texture: String::from("floor"),
+            triangles: 0..triangles.len(),
+            sampler: Sampler::CLAMP,
+            texel_uvs: false,
+        }];
+
         Self {
-            triangles: triangles,
+            triangles,
+            surfaces,
+            sprites,
+            player_start: None,
+        }
+    }
+
+    pub fn from_map(map: &Map) -> Self {
+        let mut world = Self {
+            triangles: vec![],
+            surfaces: vec![],
+            sprites: vec![],
+            player_start: None,
+        };
+
+        for linedef in &map.linedefs {
+            let v1 = map_point(map, linedef.v1);
+            let v2 = map_point(map, linedef.v2);
+            let front = &map.sidedefs[linedef.front as usize];
+            let back = map.back_sidedef(linedef);
+
+            let front_sector = &map.sectors[front.sector as usize];
+            let back_sector = back.map(|back| &map.sectors[back.sector as usize]);
+
+            world.add_side(v1, v2, front, front_sector, back_sector);
+            if let (Some(back), Some(back_sector)) = (back, back_sector) {
+                world.add_side(v2, v1, back, back_sector, Some(front_sector));
+            }
+        }
+
+        let sector_edges = collect_sector_edges(map);
+        for (sector, edges) in map.sectors.iter().zip(&sector_edges) {
+            world.add_flats(sector, edges);
+        }
+
+        for thing in &map.things {
+            let point = Vector2::new(thing.x as f32, thing.y as f32);
+            let floor_height = sector_edges
+                .iter()
+                .position(|edges| contains_point(edges, point))
+                .map_or(0.0, |sector| map.sectors[sector].floor_height as f32);
+
+            if thing.kind == PLAYER_START {
+                // Doom angles start at east, the player yaw starts at north
+                world.player_start = Some((
+                    world_position(point, floor_height + PLAYER_EYE_HEIGHT).truncate(),
+                    (thing.angle as f32).to_radians() - FRAC_PI_2,
+                ));
+            }
+            if thing_sprite(thing.kind).is_none() {
+                continue;
+            }
+
+            world.sprites.push(SpritePlacement {
+                pos: world_position(point, floor_height),
+                size: THING_SIZE * MAP_SCALE,
+                kind: thing.kind,
+            });
         }
+
+        world
     }
+
+    fn add_side(
+        &mut self,
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+        side: &Sidedef,
+        sector: &Sector,
+        other: Option<&Sector>,
+    ) {
+        let floor = sector.floor_height as f32;
+        let ceiling = sector.ceiling_height as f32;
+        let color = light_color(sector);
+
+        match other {
+            None => self.add_wall(start, end, (floor, ceiling), side, &side.middle, color),
+            Some(other) => {
+                let other_floor = other.floor_height as f32;
+                let other_ceiling = other.ceiling_height as f32;
+
+                if other_floor > floor {
+                    self.add_wall(start, end, (floor, other_floor), side, &side.lower, color);
+                }
+                // Upper walls between two sky ceilings would cover the sky
+                let both_sky = sector.ceiling_flat == SKY_FLAT && other.ceiling_flat == SKY_FLAT;
+                if other_ceiling < ceiling && !both_sky {
+                    self.add_wall(
+                        start,
+                        end,
+                        (other_ceiling, ceiling),
+                        side,
+                        &side.upper,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+
+    // Walls are seen from the front side, where start is on the left
+    fn add_wall(
+        &mut self,
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+        (bottom, top): (f32, f32),
+        side: &Sidedef,
+        texture: &str,
+        color: Vector3<f32>,
+    ) {
+        if texture == NO_TEXTURE || bottom >= top {
+            return;
+        }
+
+        // One map unit is one texel, textures hang from the top of the wall
+        let left = side.x_offset as f32;
+        let right = left + (end - start).magnitude();
+        let upper = side.y_offset as f32;
+        let lower = upper + top - bottom;
+
+        let vertex = |point, height, uv| Vertex {
+            pos: world_position(point, height),
+            color,
+            uv,
+        };
+        let top_left = vertex(start, top, Vector2::new(left, upper));
+        let top_right = vertex(end, top, Vector2::new(right, upper));
+        let bottom_left = vertex(start, bottom, Vector2::new(left, lower));
+        let bottom_right = vertex(end, bottom, Vector2::new(right, lower));
+
+        let first = self.triangles.len();
+        self.triangles.push((top_right, bottom_right, bottom_left));
+        self.triangles.push((top_left, top_right, bottom_left));
+        self.push_surface(texture, first);
+    }
+
+    fn add_flats(&mut self, sector: &Sector, edges: &[(Vector2<f32>, Vector2<f32>)]) {
+        let triangles = triangulate_sector(edges);
+        if triangles.is_empty() {
+            return;
+        }
+
+        // Flats are aligned to the map grid so neighboring sectors line up
+        let color = light_color(sector);
+        let vertex = |point: Vector2<f32>, height: i16| Vertex {
+            pos: world_position(point, height as f32),
+            color,
+            uv: Vector2::new(point.x, -point.y),
+        };
+
+        // Triangles come out counter-clockwise, which only faces down
+        let first = self.triangles.len();
+        for (a, b, c) in &triangles {
+            self.triangles.push((
+                vertex(*a, sector.floor_height),
+                vertex(*c, sector.floor_height),
+                vertex(*b, sector.floor_height),
+            ));
+        }
+        self.push_surface(&sector.floor_flat, first);
+
+        let first = self.triangles.len();
+        for (a, b, c) in &triangles {
+            self.triangles.push((
+                vertex(*a, sector.ceiling_height),
+                vertex(*b, sector.ceiling_height),
+                vertex(*c, sector.ceiling_height),
+            ));
+        }
+        self.push_surface(&sector.ceiling_flat, first);
+    }
+
+    // Divides texel UVs by the size of the texture each surface ended up with
+    pub fn normalize_uvs(&mut self, texture_size: impl Fn(usize) -> (i32, i32)) {
+        for (index, surface) in self.surfaces.iter_mut().enumerate() {
+            if !surface.texel_uvs {
+                continue;
+            }
+            surface.texel_uvs = false;
+
+            let (width, height) = texture_size(index);
+            let scale = Vector2::new(1.0 / width.max(1) as f32, 1.0 / height.max(1) as f32);
+            for (v0, v1, v2) in &mut self.triangles[surface.triangles.clone()] {
+                for vertex in [v0, v1, v2] {
+                    vertex.uv = Vector2::new(vertex.uv.x * scale.x, vertex.uv.y * scale.y);
+                }
+            }
+        }
+    }
+
+    fn push_surface(&mut self, texture: &str, first: usize) {
+        self.surfaces.push(Surface {
+            texture: texture.to_string(),
+            triangles: first..self.triangles.len(),
+            sampler: Sampler::REPEAT,
+            texel_uvs: true,
+        });
+    }
+}
+
+fn map_point(map: &Map, index: u16) -> Vector2<f32> {
+    let vertex = map.vertexes[index as usize];
+    Vector2::new(vertex.x as f32, vertex.y as f32)
+}
+
+// Map north points away from the default view, so Y is flipped into -Z
+fn world_position(point: Vector2<f32>, height: f32) -> Vector4<f32> {
+    Vector4::new(
+        point.x * MAP_SCALE,
+        height * MAP_SCALE,
+        -point.y * MAP_SCALE,
+        1.0,
+    )
+}
+
+fn thing_sprite(kind: u16) -> Option<&'static str> {
+    THING_SPRITES
+        .iter()
+        .find(|(thing_kind, _)| *thing_kind == kind)
+        .map(|(_, sprite)| *sprite)
+}
+
+fn light_color(sector: &Sector) -> Vector3<f32> {
+    let light = sector.light_level.clamp(0, 255) as f32 / 255.0;
+    Vector3::new(light, light, light)
+}
+
+// Every edge keeps its sector on the right, so outer boundaries wind clockwise
+fn collect_sector_edges(map: &Map) -> Vec<Vec<(Vector2<f32>, Vector2<f32>)>> {
+    let mut sector_edges = Vec::from_iter(map.sectors.iter().map(|_| vec![]));
+
+    for linedef in &map.linedefs {
+        let v1 = map_point(map, linedef.v1);
+        let v2 = map_point(map, linedef.v2);
+        let front = map.sidedefs[linedef.front as usize].sector as usize;
+        let back = map.back_sidedef(linedef).map(|back| back.sector as usize);
+
+        // Lines with the same sector on both sides don't bound anything
+        if back == Some(front) {
+            continue;
+        }
+
+        sector_edges[front].push((v1, v2));
+        if let Some(back) = back {
+            sector_edges[back].push((v2, v1));
+        }
+    }
+
+    sector_edges
+}
+
+fn contains_point(edges: &[(Vector2<f32>, Vector2<f32>)], point: Vector2<f32>) -> bool {
+    edges.iter().fold(false, |inside, (a, b)| {
+        let crosses = (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y);
+        inside ^ crosses
+    })
+}
+
+fn triangulate_sector(
+    edges: &[(Vector2<f32>, Vector2<f32>)],
+) -> Vec<(Vector2<f32>, Vector2<f32>, Vector2<f32>)> {
+    let loops = build_loops(edges);
+
+    // Reversed so that outer boundaries are counter-clockwise and holes clockwise
+    let (mut outers, holes): (Vec<_>, Vec<_>) = loops
+        .into_iter()
+        .map(|mut polygon| {
+            polygon.reverse();
+            polygon
+        })
+        .partition(|polygon| signed_area(polygon) > 0.0);
+
+    let mut outer_holes = Vec::from_iter(outers.iter().map(|_| vec![]));
+    for hole in holes {
+        let owner = outers
+            .iter()
+            .enumerate()
+            .filter(|(_, outer)| polygon_contains(outer, hole[0]))
+            .min_by(|(_, a), (_, b)| signed_area(a).total_cmp(&signed_area(b)))
+            .map(|(index, _)| index);
+        if let Some(owner) = owner {
+            outer_holes[owner].push(hole);
+        }
+    }
+
+    let mut triangles = vec![];
+    for (outer, holes) in outers.iter_mut().zip(outer_holes) {
+        let polygon = bridge_holes(std::mem::take(outer), holes);
+        triangles.extend(ear_clip(&polygon));
+    }
+    triangles
+}
+
+fn build_loops(edges: &[(Vector2<f32>, Vector2<f32>)]) -> Vec<Vec<Vector2<f32>>> {
+    let mut used = vec![false; edges.len()];
+    let mut loops = vec![];
+
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+
+        let (start, mut current) = edges[first];
+        let mut polygon = vec![start];
+        while current != start {
+            let next = (0..edges.len()).find(|&i| !used[i] && edges[i].0 == current);
+            match next {
+                Some(next) => {
+                    used[next] = true;
+                    polygon.push(current);
+                    current = edges[next].1;
+                }
+                None => break,
+            }
+        }
+
+        // Unclosed chains come from broken maps and can't be filled
+        if current == start && polygon.len() >= 3 {
+            loops.push(polygon);
+        }
+    }
+
+    loops
+}
+
+fn signed_area(polygon: &[Vector2<f32>]) -> f32 {
+    (0..polygon.len()).fold(0.0, |area, i| {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        area + (a.x * b.y - b.x * a.y) / 2.0
+    })
+}
+
+fn polygon_contains(polygon: &[Vector2<f32>], point: Vector2<f32>) -> bool {
+    let edges =
+        Vec::from_iter((0..polygon.len()).map(|i| (polygon[i], polygon[(i + 1) % polygon.len()])));
+    contains_point(&edges, point)
+}
+
+fn cross(o: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+fn segments_cross(a0: Vector2<f32>, a1: Vector2<f32>, b0: Vector2<f32>, b1: Vector2<f32>) -> bool {
+    let d0 = cross(a0, a1, b0);
+    let d1 = cross(a0, a1, b1);
+    let d2 = cross(b0, b1, a0);
+    let d3 = cross(b0, b1, a1);
+    d0 * d1 < 0.0 && d2 * d3 < 0.0
+}
+
+// Each hole is cut open towards a visible outer vertex, starting with the rightmost hole
+fn bridge_holes(
+    mut polygon: Vec<Vector2<f32>>,
+    mut holes: Vec<Vec<Vector2<f32>>>,
+) -> Vec<Vector2<f32>> {
+    let rightmost = |hole: &Vec<Vector2<f32>>| {
+        (0..hole.len())
+            .max_by(|&a, &b| hole[a].x.total_cmp(&hole[b].x))
+            .unwrap_or(0)
+    };
+    holes.sort_by(|a, b| hole_max_x(b).total_cmp(&hole_max_x(a)));
+
+    for (index, hole) in holes.iter().enumerate() {
+        let m = rightmost(hole);
+        let hole_point = hole[m];
+
+        let blocked = |target: Vector2<f32>| {
+            let mut edges = (0..polygon.len())
+                .map(|i| (polygon[i], polygon[(i + 1) % polygon.len()]))
+                .chain(holes[index..].iter().flat_map(|hole| {
+                    (0..hole.len()).map(move |i| (hole[i], hole[(i + 1) % hole.len()]))
+                }));
+            edges.any(|(a, b)| segments_cross(hole_point, target, a, b))
+        };
+
+        let candidate = (0..polygon.len())
+            .filter(|&i| !blocked(polygon[i]))
+            .min_by(|&a, &b| {
+                let distance = |i: usize| {
+                    let d = polygon[i] - hole_point;
+                    d.x * d.x + d.y * d.y
+                };
+                distance(a).total_cmp(&distance(b))
+            });
+        let Some(p) = candidate else {
+            continue;
+        };
+
+        let mut bridged = Vec::with_capacity(polygon.len() + hole.len() + 2);
+        bridged.extend_from_slice(&polygon[..=p]);
+        bridged.extend((0..=hole.len()).map(|i| hole[(m + i) % hole.len()]));
+        bridged.extend_from_slice(&polygon[p..]);
+        polygon = bridged;
+    }
+
+    polygon
+}
+
+fn hole_max_x(hole: &[Vector2<f32>]) -> f32 {
+    hole.iter().fold(f32::MIN, |max, point| max.max(point.x))
+}
+
+fn ear_clip(polygon: &[Vector2<f32>]) -> Vec<(Vector2<f32>, Vector2<f32>, Vector2<f32>)> {
+    let mut indices = Vec::from_iter(0..polygon.len());
+    let mut triangles = vec![];
+
+    while indices.len() > 3 {
+        let corner = |i: usize| {
+            let count = indices.len();
+            (
+                polygon[indices[(i + count - 1) % count]],
+                polygon[indices[i]],
+                polygon[indices[(i + 1) % count]],
+            )
+        };
+
+        let is_ear = |i: usize| {
+            let (a, b, c) = corner(i);
+            cross(a, b, c) > 0.0
+                && !indices.iter().any(|&j| {
+                    let p = polygon[j];
+                    p != a
+                        && p != b
+                        && p != c
+                        && cross(a, b, p) >= 0.0
+                        && cross(b, c, p) >= 0.0
+                        && cross(c, a, p) >= 0.0
+                })
+        };
+
+        if let Some(ear) = (0..indices.len()).find(|&i| is_ear(i)) {
+            triangles.push(corner(ear));
+            indices.remove(ear);
+        } else if let Some(flat) = (0..indices.len()).find(|&i| {
+            let (a, b, c) = corner(i);
+            cross(a, b, c) == 0.0
+        }) {
+            // Collinear points add no area and would otherwise stall the search
+            indices.remove(flat);
+        } else {
+            break;
+        }
+    }
+
+    if indices.len() == 3 {
+        let (a, b, c) = (
+            polygon[indices[0]],
+            polygon[indices[1]],
+            polygon[indices[2]],
+        );
+        if cross(a, b, c) > 0.0 {
+            triangles.push((a, b, c));
+        }
+    }
+
+    triangles
 }
@@ -1,14 +1,16 @@
 mod game;
 
 use common::{
+    assets::AssetManager,
     buffer2d::{
-        text::{blit_str_wrap, Font},
+        layout::{Align, LayoutRules, TextLayout},
+        text::{blit_str_wrap, Font, TextEffect, TextStyle},
         virtual_window::{VirtualWindow, VirtualWindowStack, WindowBorder},
     },
     console::Console,
-    image::bmp,
     platform::init_application,
     renderer::{camera::Camera, Renderer},
+    wad::Wad,
 };
 use game::{
     definitions::{
@@ -20,7 +22,12 @@ use game::{
     Game, GameState,
 };
 
-pub fn create_virtual_windows() -> Vec<VirtualWindow> {
+const ASSET_DIRECTORY: &str = "./assets";
+
+const MAP_WAD_PATH: &str = "./assets/map.wad";
+const MAP_NAME: &str = "E1M1";
+
+pub fn create_virtual_windows(font: &Font) -> Vec<VirtualWindow> {
     let mut virtual_windows = vec![];
     for i in 0..VW_MAX {
         virtual_windows.push(match i {
@@ -33,26 +40,87 @@ pub fn create_virtual_windows() -> Vec<VirtualWindow> {
             }
             VW_TEST_A => VirtualWindow::new(String::from("Test A"), TEST_A_WIDTH, TEST_A_HEIGHT)
                 .with_xyz(64, 32, 1),
-            VW_TEST_B => VirtualWindow::new(String::from("Test B"), TEST_A_WIDTH, TEST_A_HEIGHT)
-                .with_xyz(200, 40, 2),
+            VW_TEST_B => {
+                let rules = LayoutRules {
+                    align: Align::Justify,
+                    ..Default::default()
+                };
+                let layout = TextLayout::new(
+                    font,
+                    "This window is sized to fit its text, which is laid out before it is drawn",
+                    TEST_A_WIDTH - 24,
+                    rules,
+                    TextStyle::default(),
+                );
+                VirtualWindow::from_text(String::from("Test B"), font, &layout, 12)
+                    .with_xyz(200, 40, 2)
+            }
             _ => unreachable!(),
         });
     }
     virtual_windows
 }
 
+// Falls back to the test geometry so the game stays playable without a WAD
+fn load_world(assets: &mut AssetManager, errors: &mut Vec<String>) -> World {
+    match Wad::open(MAP_WAD_PATH).and_then(|mut wad| wad.read_map(MAP_NAME)) {
+        Ok(map) => {
+            // The map WAD doubles as an archive for its flats and sprites
+            if let Err(error) = assets.add_archive(MAP_WAD_PATH) {
+                errors.push(format!("Failed to add \"{}\": {}", MAP_WAD_PATH, error));
+            }
+            World::from_map(&map)
+        }
+        Err(error) => {
+            errors.push(format!(
+                "Failed to load map \"{}\" from \"{}\": {}",
+                MAP_NAME, MAP_WAD_PATH, error
+            ));
+            World::new()
+        }
+    }
+}
+
 fn load_game() -> Game {
-    let world = World::new();
-    let texture = bmp::load_bmp("./assets/floor.bmp");
-    let crusader = bmp::load_bmp("./assets/crusader.bmp");
-    let border = WindowBorder::new(bmp::load_bmp("./assets/border.bmp"));
-    let font = Font::new(bmp::load_bmp("./assets/conchars.bmp"), (8, 8), 0, 2);
-    let console_font = Font::new(bmp::load_bmp("./assets/conchars.bmp"), (8, 8), 0, 10);
+    let mut errors = vec![];
 
-    let mut console = Console::new(REFERENCE_WIDTH / 3, REFERENCE_HEIGHT, console_font);
+    let mut assets = AssetManager::new();
+    assets.add_directory(ASSET_DIRECTORY);
+
+    let mut world = load_world(&mut assets, &mut errors);
+    let world_textures = Vec::from_iter(
+        world
+            .surfaces
+            .iter()
+            .map(|surface| assets.load_texture(&surface.texture)),
+    );
+    world.normalize_uvs(|surface| {
+        let texture = assets.texture(world_textures[surface]);
+        (texture.width, texture.height)
+    });
+    let sprite_textures = Vec::from_iter(
+        world
+            .sprites
+            .iter()
+            .map(|sprite| assets.load_texture(sprite.sprite())),
+    );
+    let border = assets.load_texture("border");
+    let border = WindowBorder::new(assets.texture(border).clone());
+    let font = assets.load_font("conchars", (8, 8), 0, 2);
+    let console_font = assets.load_font("conchars", (8, 8), 0, 10);
+
+    let mut console = Console::new(
+        REFERENCE_WIDTH / 3,
+        REFERENCE_HEIGHT,
+        assets.font(console_font),
+    );
     console.put_line("Console activated");
+    assets.report(&mut console);
+    for error in errors {
+        console.put_error(&error);
+    }
 
-    let virtual_windows = create_virtual_windows();
+    let virtual_windows = create_virtual_windows(&assets.font(font));
     for virtual_window in &virtual_windows {
         console.put_string(format!(
             "Virtual window \"{}\" created",
@@ -60,12 +128,13 @@ fn load_game() -> Game {
         ));
     }
     blit_str_wrap(
-        &font,
+        &assets.font(font),
         &mut virtual_windows[VW_TEST_A].buffer.borrow_mut(),
         "Wrapped text Wrapped text Wrapped text Wrapped text ",
         (12, 12),
         0,
         false,
+        TextStyle::default().with_effect(TextEffect::Shadow(0)),
     );
     let renderer = Renderer::new(&virtual_windows[VW_PRIMARY].buffer);
     console.put_line("Renderer created");
@@ -81,7 +150,11 @@ fn load_game() -> Game {
         0.01,
         100.0,
     );
-    let player = Player::new();
+    let mut player = Player::new();
+    if let Some((pos, yaw)) = world.player_start {
+        player.pos = pos;
+        player.yaw = yaw;
+    }
 
     let x = 0;
     let y = 0;
@@ -94,8 +167,9 @@ fn load_game() -> Game {
         camera,
         player,
         world,
-        texture,
-        crusader,
+        assets,
+        world_textures,
+        sprite_textures,
         border,
         font,
         x,
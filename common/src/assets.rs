@@ -0,0 +1,481 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+use crate::{
+    buffer2d::{
+        dither::Dither, mip_chain::MipChain, pixel::Indexed8, text::Font, B2DO, MASK_COLOR,
+    },
+    console::{Console, Severity},
+    font::load_font,
+    image::{load_image, load_image_from_bytes, ImageFormat},
+    renderer::indexed::{IndexedPalette, Quantizer},
+    utils::color_from_tuple,
+    wad::{
+        graphics::{decode_flat, decode_patch, Palette},
+        Wad, WadError,
+    },
+};
+
+pub const MISSING_TEXTURE_SIZE: i32 = 128;
+pub const MISSING_TEXTURE_COLOR: u16 = color_from_tuple((31, 0, 31));
+
+// Names without an extension are tried with each of these in order
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "bmp", "tga", "pcx"];
+const FONT_EXTENSIONS: [&str; 3] = ["bdf", "psf", "psfu"];
+
+const HOT_RELOAD_INTERVAL: f32 = 0.5;
+
+const FLAT_MARKERS: [(&str, &str); 2] = [("F_START", "F_END"), ("FF_START", "FF_END")];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FontHandle(usize);
+
+struct WatchedFile {
+    handle: TextureHandle,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+// Images that went through dithering on import, WAD graphics use their palette as is
+enum ImageSource {
+    File(PathBuf),
+    Lump { archive: usize, lump: usize },
+}
+
+struct Archive {
+    path: String,
+    wad: Wad<BufReader<File>>,
+    palette: Option<Palette>,
+}
+
+pub struct AssetManager {
+    directories: Vec<PathBuf>,
+    archives: Vec<Archive>,
+
+    textures: Vec<MipChain>,
+    // Filled while a palette is set, in step with textures
+    indexed_textures: Vec<MipChain<Indexed8>>,
+    quantizer: Option<Quantizer>,
+    texture_names: HashMap<String, TextureHandle>,
+    image_sources: Vec<(TextureHandle, ImageSource)>,
+    fonts: Vec<Rc<Font>>,
+    font_keys: HashMap<(String, (i32, i32), i32, i32), FontHandle>,
+    font_files: HashMap<String, FontHandle>,
+
+    watched_files: Vec<WatchedFile>,
+    hot_reload_timer: f32,
+    dither: Dither,
+
+    messages: Vec<(Severity, String)>,
+}
+
+impl Default for AssetManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        Self {
+            directories: vec![],
+            archives: vec![],
+
+            // The first texture is what every missing texture resolves to
+            textures: vec![MipChain::new(missing_texture())],
+            indexed_textures: vec![],
+            quantizer: None,
+            texture_names: HashMap::new(),
+            image_sources: vec![],
+            fonts: vec![],
+            font_keys: HashMap::new(),
+            font_files: HashMap::new(),
+
+            watched_files: vec![],
+            hot_reload_timer: 0.0,
+            dither: Dither::None,
+
+            messages: vec![],
+        }
+    }
+
+    pub fn add_directory(&mut self, path: &str) {
+        self.directories.push(PathBuf::from(path));
+    }
+
+    pub fn add_archive(&mut self, path: &str) -> Result<(), WadError> {
+        let mut wad = Wad::open(path)?;
+        let palette = match wad.find_lump("PLAYPAL") {
+            Some(_) => wad.read_playpal()?.first().copied(),
+            None => None,
+        };
+
+        self.archives.push(Archive {
+            path: path.to_string(),
+            wad,
+            palette,
+        });
+
+        Ok(())
+    }
+
+    pub fn missing_texture(&self) -> TextureHandle {
+        TextureHandle(0)
+    }
+
+    pub fn load_texture(&mut self, name: &str) -> TextureHandle {
+        if let Some(handle) = self.texture_names.get(name) {
+            return *handle;
+        }
+
+        let handle = match self.find_texture(name) {
+            Ok(Some((texture, source))) => {
+                self.textures.push(MipChain::new(texture));
+                self.push_indexed(self.textures.len() - 1);
+                let handle = TextureHandle(self.textures.len() - 1);
+                // Only loose files can change on disk, archives are left alone
+                if let Some(ImageSource::File(path)) = &source {
+                    self.watched_files.push(WatchedFile {
+                        handle,
+                        modified: modified_time(path),
+                        path: path.clone(),
+                    });
+                }
+                if let Some(source) = source {
+                    self.image_sources.push((handle, source));
+                }
+                handle
+            }
+            Ok(None) => {
+                self.messages.push((
+                    Severity::Warning,
+                    format!("Texture \"{}\" was not found", name),
+                ));
+                self.missing_texture()
+            }
+            Err(error) => {
+                self.messages.push((
+                    Severity::Error,
+                    format!("Failed to load texture \"{}\": {}", name, error),
+                ));
+                self.missing_texture()
+            }
+        };
+
+        // Missing textures are cached too, so they are only reported once
+        self.texture_names.insert(name.to_string(), handle);
+        handle
+    }
+
+    pub fn load_font(
+        &mut self,
+        name: &str,
+        glyph_size: (i32, i32),
+        offset_x: i32,
+        offset_y: i32,
+    ) -> FontHandle {
+        let key = (name.to_string(), glyph_size, offset_x, offset_y);
+        if let Some(handle) = self.font_keys.get(&key) {
+            return *handle;
+        }
+
+        let texture = self.load_texture(name);
+        let font = Font::new(
+            self.texture(texture).clone(),
+            glyph_size,
+            offset_x,
+            offset_y,
+        );
+        self.fonts.push(Rc::new(font));
+
+        let handle = FontHandle(self.fonts.len() - 1);
+        self.font_keys.insert(key, handle);
+        handle
+    }
+
+    // BDF and PSF fonts from the asset directories, failures are reported and give None
+    pub fn load_font_file(&mut self, name: &str) -> Option<FontHandle> {
+        if let Some(handle) = self.font_files.get(name) {
+            return Some(*handle);
+        }
+
+        let Some(path) = self
+            .directories
+            .iter()
+            .find_map(|directory| find_in_directory(directory, name, &FONT_EXTENSIONS))
+        else {
+            self.messages.push((
+                Severity::Warning,
+                format!("Font \"{}\" was not found", name),
+            ));
+            return None;
+        };
+
+        match load_font(&path.to_string_lossy()) {
+            Ok(font) => {
+                self.fonts.push(Rc::new(font));
+                let handle = FontHandle(self.fonts.len() - 1);
+                self.font_files.insert(name.to_string(), handle);
+                Some(handle)
+            }
+            Err(error) => {
+                self.messages.push((
+                    Severity::Error,
+                    format!(
+                        "Failed to load font \"{}\": {} ({})",
+                        name,
+                        error,
+                        path.display()
+                    ),
+                ));
+                None
+            }
+        }
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> &B2DO {
+        self.textures[handle.0].base()
+    }
+
+    pub fn mip_chain(&self, handle: TextureHandle) -> &MipChain {
+        &self.textures[handle.0]
+    }
+
+    pub fn indexed_mip_chain(&self, handle: TextureHandle) -> Option<&MipChain<Indexed8>> {
+        self.indexed_textures.get(handle.0)
+    }
+
+    // Keeps an indexed copy of every texture for the palette render path, None drops them
+    pub fn set_palette(&mut self, palette: Option<&Palette>) {
+        self.quantizer = palette.map(Quantizer::new);
+        self.indexed_textures.clear();
+        for index in 0..self.textures.len() {
+            self.push_indexed(index);
+        }
+    }
+
+    // PLAYPAL and COLORMAP from the last archive that has them, COLORMAP may be missing
+    pub fn read_indexed_palette(&mut self) -> Result<Option<IndexedPalette>, WadError> {
+        for archive in self.archives.iter_mut().rev() {
+            if archive.wad.find_lump("PLAYPAL").is_none() {
+                continue;
+            }
+
+            let palettes = archive.wad.read_playpal()?;
+            let colormaps = match archive.wad.find_lump("COLORMAP") {
+                Some(_) => archive.wad.read_colormap()?,
+                None => vec![],
+            };
+            return Ok(IndexedPalette::new(palettes, colormaps));
+        }
+
+        Ok(None)
+    }
+
+    pub fn font(&self, handle: FontHandle) -> Rc<Font> {
+        self.fonts[handle.0].clone()
+    }
+
+    pub fn dither(&self) -> Dither {
+        self.dither
+    }
+
+    // Images are imported again from their source, nothing else is touched
+    pub fn set_dither(&mut self, dither: Dither) {
+        if dither == self.dither {
+            return;
+        }
+        self.dither = dither;
+
+        let image_sources = std::mem::take(&mut self.image_sources);
+        for (handle, source) in &image_sources {
+            match self.import_image(source) {
+                Ok(texture) => self.replace_texture(*handle, texture),
+                Err(error) => self.messages.push((
+                    Severity::Error,
+                    format!("Failed to dither texture: {}", error),
+                )),
+            }
+        }
+        self.image_sources = image_sources;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.hot_reload_timer += dt;
+        if self.hot_reload_timer < HOT_RELOAD_INTERVAL {
+            return;
+        }
+        self.hot_reload_timer = 0.0;
+
+        self.reload_changed();
+    }
+
+    // Failed reloads keep the old pixels, the next save will be picked up again
+    pub fn reload_changed(&mut self) {
+        for index in 0..self.watched_files.len() {
+            let watched = &mut self.watched_files[index];
+            let modified = modified_time(&watched.path);
+            if modified == watched.modified {
+                continue;
+            }
+            watched.modified = modified;
+
+            let (handle, path) = (watched.handle, watched.path.clone());
+            match load_image(&path.to_string_lossy(), self.dither) {
+                Ok(texture) => {
+                    self.replace_texture(handle, texture);
+                    self.messages
+                        .push((Severity::Info, format!("Reloaded \"{}\"", path.display())));
+                }
+                Err(error) => self.messages.push((
+                    Severity::Error,
+                    format!("Failed to reload \"{}\": {}", path.display(), error),
+                )),
+            }
+        }
+    }
+
+    pub fn report(&mut self, console: &mut Console) {
+        for (severity, message) in self.messages.drain(..) {
+            console.log(severity, &message);
+        }
+    }
+
+    fn replace_texture(&mut self, handle: TextureHandle, texture: B2DO) {
+        self.textures[handle.0] = MipChain::new(texture);
+        if let Some(quantizer) = &mut self.quantizer {
+            self.indexed_textures[handle.0] =
+                self.textures[handle.0].map_pixels(|color| quantizer.index(color));
+        }
+    }
+
+    fn import_image(&mut self, source: &ImageSource) -> Result<B2DO, String> {
+        match source {
+            ImageSource::File(path) => load_image(&path.to_string_lossy(), self.dither)
+                .map_err(|error| format!("{} ({})", error, path.display())),
+            ImageSource::Lump { archive, lump } => {
+                let archive = &mut self.archives[*archive];
+                let describe =
+                    |error: &dyn std::fmt::Display| format!("{} ({})", error, archive.path);
+                let data = archive
+                    .wad
+                    .read_lump(*lump)
+                    .map_err(|error| describe(&error))?;
+                load_image_from_bytes(&data, self.dither).map_err(|error| describe(&error))
+            }
+        }
+    }
+
+    fn push_indexed(&mut self, index: usize) {
+        if let Some(quantizer) = &mut self.quantizer {
+            let indexed = self.textures[index].map_pixels(|color| quantizer.index(color));
+            self.indexed_textures.push(indexed);
+        }
+    }
+
+    // Directories are searched before archives, later archives override earlier ones
+    fn find_texture(&mut self, name: &str) -> Result<Option<(B2DO, Option<ImageSource>)>, String> {
+        if let Some(path) = self
+            .directories
+            .iter()
+            .find_map(|directory| find_in_directory(directory, name, &IMAGE_EXTENSIONS))
+        {
+            let source = ImageSource::File(path);
+            return self
+                .import_image(&source)
+                .map(|texture| Some((texture, Some(source))));
+        }
+
+        let lump_name = Path::new(name)
+            .file_stem()
+            .map_or(name.to_string(), |stem| stem.to_string_lossy().into_owned());
+        let fallback_palette = self.archives.iter().find_map(|archive| archive.palette);
+
+        for (archive_index, archive) in self.archives.iter_mut().enumerate().rev() {
+            let Some(index) = archive.wad.find_lump(&lump_name) else {
+                continue;
+            };
+
+            let describe = |error: &dyn std::fmt::Display| format!("{} ({})", error, archive.path);
+            let data = archive
+                .wad
+                .read_lump(index)
+                .map_err(|error| describe(&error))?;
+
+            // Signatures are checked first, TGA has none and would match raw graphics
+            if matches!(
+                ImageFormat::detect(&data),
+                Some(ImageFormat::Bmp | ImageFormat::Png | ImageFormat::Pcx)
+            ) {
+                let source = ImageSource::Lump {
+                    archive: archive_index,
+                    lump: index,
+                };
+                return load_image_from_bytes(&data, self.dither)
+                    .map(|texture| Some((texture, Some(source))))
+                    .map_err(|error| describe(&error));
+            }
+
+            let Some(palette) = archive.palette.or(fallback_palette) else {
+                return Err(describe(&"[WAD] PLAYPAL is missing!"));
+            };
+
+            let is_flat = FLAT_MARKERS.iter().any(|(start, end)| {
+                archive
+                    .wad
+                    .marker_range(start, end)
+                    .is_some_and(|range| range.contains(&index))
+            });
+            let texture = match is_flat {
+                true => decode_flat(&data, &palette),
+                false => decode_patch(&data, &palette),
+            };
+            return texture
+                .map(|texture| Some((texture, None)))
+                .map_err(|error| describe(&error));
+        }
+
+        Ok(None)
+    }
+}
+
+fn find_in_directory(directory: &Path, name: &str, extensions: &[&str]) -> Option<PathBuf> {
+    let path = directory.join(name);
+    if path.extension().is_some() {
+        return path.is_file().then_some(path);
+    }
+
+    extensions
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn missing_texture() -> B2DO {
+    let mut texture = B2DO::new(MISSING_TEXTURE_SIZE, MISSING_TEXTURE_SIZE);
+    for y in 0..texture.height {
+        for x in 0..texture.width {
+            let color = match ((x / 8) + (y / 8)) % 2 {
+                0 => MISSING_TEXTURE_COLOR,
+                _ => MASK_COLOR,
+            };
+            texture.set_color(x, y, color);
+        }
+    }
+    texture
+}
@@ -0,0 +1,67 @@
+use crate::utils::color_from_tuple;
+
+use super::MASK_COLOR;
+
+// Clears the lowest bit of every channel so halves can't borrow across channels
+const HALF_MASK: u16 = 0b0111_1011_1101_1110;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    // Plain copy, MASK_COLOR included
+    Copy,
+    Masked,
+    // Source opacity in percent
+    Alpha25,
+    Alpha50,
+    Alpha75,
+    Additive,
+    Multiply,
+    // Source multiplied by the given color
+    Tint(u16),
+}
+
+// Every mode but Copy leaves MASK_COLOR pixels alone
+pub fn blend(dest: u16, source: u16, mode: BlendMode) -> u16 {
+    if mode != BlendMode::Copy && source == MASK_COLOR {
+        return dest;
+    }
+
+    let color = match mode {
+        BlendMode::Copy | BlendMode::Masked => return source,
+        BlendMode::Alpha25 => average(dest, average(dest, source)),
+        BlendMode::Alpha50 => average(dest, source),
+        BlendMode::Alpha75 => average(source, average(dest, source)),
+        BlendMode::Additive => per_channel(dest, source, |d, s| (d + s).min(31)),
+        BlendMode::Multiply => per_channel(dest, source, |d, s| d * s / 31),
+        BlendMode::Tint(tint) => per_channel(source, tint, |s, t| s * t / 31),
+    };
+
+    // Mixed colors that land on MASK_COLOR would turn into holes once blitted masked
+    match color {
+        MASK_COLOR => MASK_COLOR - 1,
+        color => color,
+    }
+}
+
+pub fn blend_slice(dest: &mut [u16], source: &[u16], mode: BlendMode) {
+    match mode {
+        BlendMode::Copy => dest.copy_from_slice(source),
+        _ => dest
+            .iter_mut()
+            .zip(source)
+            .for_each(|(d, s)| *d = blend(*d, *s, mode)),
+    }
+}
+
+fn average(a: u16, b: u16) -> u16 {
+    ((a & HALF_MASK) >> 1) + ((b & HALF_MASK) >> 1)
+}
+
+fn per_channel(a: u16, b: u16, f: impl Fn(u16, u16) -> u16) -> u16 {
+    let channel = |c: u16, shift: u16| (c >> shift) & 0x1F;
+    color_from_tuple((
+        f(channel(a, 10), channel(b, 10)),
+        f(channel(a, 5), channel(b, 5)),
+        f(channel(a, 0), channel(b, 0)),
+    ))
+}
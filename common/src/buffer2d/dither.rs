@@ -0,0 +1,131 @@
+use cgmath::Vector3;
+
+use crate::{buffer2d::MASK_COLOR, image::color_from_rgb8};
+
+const BAYER_SIZE: usize = 4;
+const BAYER: [[u8; BAYER_SIZE]; BAYER_SIZE] =
+    [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// Ranks from a void-and-cluster pass over a 16x16 torus
+const BLUE_NOISE_SIZE: usize = 16;
+const BLUE_NOISE: [[u8; BLUE_NOISE_SIZE]; BLUE_NOISE_SIZE] = [
+    [
+        234, 50, 188, 19, 58, 171, 121, 47, 163, 1, 247, 104, 22, 132, 14, 65,
+    ],
+    [
+        209, 8, 118, 97, 240, 205, 23, 228, 138, 64, 123, 170, 72, 224, 99, 149,
+    ],
+    [
+        85, 139, 229, 165, 78, 146, 111, 84, 176, 216, 30, 231, 153, 201, 42, 180,
+    ],
+    [
+        25, 62, 195, 29, 43, 185, 7, 249, 41, 100, 191, 48, 87, 5, 128, 243,
+    ],
+    [
+        221, 152, 101, 253, 130, 220, 59, 200, 156, 12, 136, 112, 255, 174, 69, 109,
+    ],
+    [
+        46, 189, 0, 73, 172, 90, 142, 116, 80, 237, 210, 61, 147, 33, 206, 160,
+    ],
+    [
+        81, 124, 217, 113, 208, 15, 241, 27, 168, 45, 178, 20, 193, 96, 225, 18,
+    ],
+    [
+        242, 164, 60, 35, 157, 53, 181, 68, 223, 105, 125, 83, 236, 131, 55, 141,
+    ],
+    [
+        197, 10, 227, 134, 246, 95, 126, 198, 148, 3, 244, 161, 71, 9, 182, 106,
+    ],
+    [
+        40, 93, 179, 75, 192, 6, 218, 36, 91, 57, 202, 34, 215, 155, 233, 74,
+    ],
+    [
+        252, 120, 150, 24, 110, 63, 166, 119, 232, 183, 133, 103, 49, 117, 31, 167,
+    ],
+    [
+        16, 212, 51, 238, 207, 137, 254, 21, 76, 151, 13, 250, 190, 88, 203, 135,
+    ],
+    [
+        102, 184, 82, 169, 38, 89, 187, 52, 204, 98, 173, 67, 129, 4, 222, 56,
+    ],
+    [
+        230, 144, 2, 127, 226, 11, 154, 114, 239, 39, 219, 28, 235, 145, 175, 77,
+    ],
+    [
+        196, 37, 248, 70, 107, 199, 66, 177, 17, 143, 115, 159, 86, 44, 108, 26,
+    ],
+    [
+        122, 92, 158, 214, 140, 32, 245, 94, 213, 79, 194, 54, 211, 186, 251, 162,
+    ],
+];
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    Bayer,
+    BlueNoise,
+}
+
+impl Dither {
+    pub fn next(self) -> Self {
+        match self {
+            Dither::None => Dither::Bayer,
+            Dither::Bayer => Dither::BlueNoise,
+            Dither::BlueNoise => Dither::None,
+        }
+    }
+
+    // Offset in 0..1 added before truncating, the pattern repeats across the screen
+    pub fn threshold(self, x: i32, y: i32) -> f32 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Bayer => {
+                let rank = BAYER[y.rem_euclid(BAYER_SIZE as i32) as usize]
+                    [x.rem_euclid(BAYER_SIZE as i32) as usize];
+                (rank as f32 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f32
+            }
+            Dither::BlueNoise => {
+                let rank = BLUE_NOISE[y.rem_euclid(BLUE_NOISE_SIZE as i32) as usize]
+                    [x.rem_euclid(BLUE_NOISE_SIZE as i32) as usize];
+                (rank as f32 + 0.5) / (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as f32
+            }
+        }
+    }
+
+    // Channels are 0..1, without dithering this truncates like color_from_vec
+    pub fn color_from_vec(self, color: Vector3<f32>, x: i32, y: i32) -> u16 {
+        let threshold = self.threshold(x, y);
+        let channel = |c: f32| (c * 31.0 + threshold).clamp(0.0, 31.0) as u16;
+        (channel(color.x) << 10) | (channel(color.y) << 5) | channel(color.z)
+    }
+
+    // Dithers between the two nearest 5 bit levels, colors that already are one stay untouched
+    // Colors that truncate to the color key stay transparent, no other color may dither onto it
+    pub fn color_from_rgb8(self, r: u8, g: u8, b: u8, x: i32, y: i32) -> u16 {
+        let truncated = color_from_rgb8(r, g, b);
+        if self == Dither::None || truncated == MASK_COLOR {
+            return truncated;
+        }
+
+        let threshold = self.threshold(x, y);
+        let channel = |c: u8| {
+            let expand = |level: u16| (level << 3) | (level >> 2);
+            let c = c as u16;
+            let low = match c >> 3 {
+                level if expand(level) > c => level - 1,
+                level => level,
+            };
+            if low == 31 {
+                return low;
+            }
+            let fraction = (c - expand(low)) as f32 / (expand(low + 1) - expand(low)) as f32;
+            low + (fraction + threshold >= 1.0) as u16
+        };
+
+        match (channel(r) << 10) | (channel(g) << 5) | channel(b) {
+            MASK_COLOR => MASK_COLOR - 1,
+            color => color,
+        }
+    }
+}
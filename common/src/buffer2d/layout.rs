@@ -0,0 +1,362 @@
+use std::mem;
+
+use crate::buffer2d::{
+    markup::StyledChar,
+    text::{blit_char, Font, TextStyle},
+    B2D, B2DT,
+};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Wrap {
+    // Lines only break at '\n'
+    None,
+    // Breaks between words, words wider than a line are broken between characters
+    #[default]
+    Word,
+    Char,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+    // Spaces are stretched on wrapped lines, the last line of a paragraph stays left aligned
+    Justify,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LayoutRules {
+    pub wrap: Wrap,
+    pub align: Align,
+    // Lines that come from wrapping start this far in
+    pub indent: i32,
+}
+
+// Neighbouring glyphs of one line that share a color
+pub struct GlyphRun {
+    pub line: usize,
+    pub color: Option<u16>,
+    pub glyphs: Vec<(char, (i32, i32))>,
+}
+
+pub struct TextLayout {
+    pub runs: Vec<GlyphRun>,
+    pub line_count: usize,
+    pub line_height: i32,
+    // Box around the pen positions, glyph bearings and effects may reach a little outside
+    pub offset: (i32, i32),
+    pub size: (i32, i32),
+    pub style: TextStyle,
+}
+
+#[derive(Default)]
+struct Line {
+    chars: Vec<StyledChar>,
+    indented: bool,
+    wrapped: bool,
+}
+
+struct LineBreaker<'a> {
+    font: &'a Font,
+    width: i32,
+    indent: i32,
+    lines: Vec<Line>,
+    current: Line,
+    current_width: i32,
+}
+
+impl LineBreaker<'_> {
+    fn available(&self) -> i32 {
+        match self.current.indented {
+            true => self.width - self.indent,
+            false => self.width,
+        }
+    }
+
+    // The space a line was broken at is not part of either line
+    fn wrap(&mut self) {
+        while self.current.chars.last().is_some_and(|(c, _)| *c == ' ') {
+            self.current.chars.pop();
+        }
+        let next = Line {
+            indented: true,
+            ..Default::default()
+        };
+        let mut line = mem::replace(&mut self.current, next);
+        line.wrapped = true;
+        self.lines.push(line);
+        self.current_width = 0;
+    }
+
+    fn end_paragraph(&mut self) {
+        self.lines.push(mem::take(&mut self.current));
+        self.current_width = 0;
+    }
+
+    // A line always takes at least one character, so narrow widths can't stall
+    fn push_char(&mut self, styled: StyledChar) {
+        let advance = self.font.advance(styled.0);
+        if self.current_width + advance > self.available() && !self.current.chars.is_empty() {
+            self.wrap();
+            if styled.0 == ' ' {
+                return;
+            }
+        }
+        self.current.chars.push(styled);
+        self.current_width += advance;
+    }
+
+    // Trailing spaces may hang past the width, they are dropped if the line wraps after them
+    fn push_word(&mut self, word: &[StyledChar]) {
+        let width = styled_width(self.font, word);
+        let word_width = match word.last() {
+            Some((' ', _)) => width - self.font.advance(' '),
+            _ => width,
+        };
+
+        if self.current_width + word_width > self.available() && !self.current.chars.is_empty() {
+            self.wrap();
+        }
+        if word_width > self.available() {
+            for styled in word {
+                self.push_char(*styled);
+            }
+            return;
+        }
+
+        self.current.chars.extend_from_slice(word);
+        self.current_width += width;
+    }
+}
+
+impl TextLayout {
+    pub fn new(font: &Font, text: &str, width: i32, rules: LayoutRules, style: TextStyle) -> Self {
+        let styled = style.apply(text);
+        let mut breaker = LineBreaker {
+            font,
+            width,
+            indent: rules.indent,
+            lines: vec![],
+            current: Line::default(),
+            current_width: 0,
+        };
+
+        for paragraph in styled.split(|(c, _)| *c == '\n') {
+            match rules.wrap {
+                Wrap::None => breaker.current.chars.extend_from_slice(paragraph),
+                Wrap::Word => {
+                    for word in paragraph.split_inclusive(|(c, _)| *c == ' ') {
+                        breaker.push_word(word);
+                    }
+                }
+                Wrap::Char => {
+                    for styled in paragraph {
+                        breaker.push_char(*styled);
+                    }
+                }
+            }
+            breaker.end_paragraph();
+        }
+
+        let line_height = font.glyph_size.1;
+        let mut runs: Vec<GlyphRun> = vec![];
+        let mut left = i32::MAX;
+        let mut right = i32::MIN;
+
+        for (index, line) in breaker.lines.iter().enumerate() {
+            let start = match line.indented {
+                true => rules.indent,
+                false => 0,
+            };
+            let slack = width - start - styled_width(font, &line.chars);
+            let spaces = line.chars.iter().filter(|(c, _)| *c == ' ').count() as i32;
+
+            let mut x = match rules.align {
+                Align::Left | Align::Justify => start,
+                Align::Center => start + slack / 2,
+                Align::Right => start + slack,
+            };
+            let justify = rules.align == Align::Justify && line.wrapped && spaces > 0 && slack > 0;
+            let y = index as i32 * line_height;
+
+            left = left.min(x);
+            let mut space_index = 0;
+            for &(c, color) in &line.chars {
+                if font.glyph(c).is_some() {
+                    match runs.last_mut() {
+                        Some(run) if run.line == index && run.color == color => {
+                            run.glyphs.push((c, (x, y)))
+                        }
+                        _ => runs.push(GlyphRun {
+                            line: index,
+                            color,
+                            glyphs: vec![(c, (x, y))],
+                        }),
+                    }
+                }
+
+                x += font.advance(c);
+                // Spread the slack so the gaps differ by at most a pixel
+                if justify && c == ' ' {
+                    x += slack * (space_index + 1) / spaces - slack * space_index / spaces;
+                    space_index += 1;
+                }
+            }
+            right = right.max(x);
+        }
+
+        let line_count = breaker.lines.len();
+        Self {
+            runs,
+            line_count,
+            line_height,
+            offset: (left, 0),
+            size: (right - left, line_count as i32 * line_height),
+            style,
+        }
+    }
+
+    pub fn draw<T: B2DT<Pixel = u16>>(&self, font: &Font, dest: &mut B2D<T>, offset: (i32, i32)) {
+        for run in &self.runs {
+            self.draw_run(font, dest, run, offset);
+        }
+    }
+
+    pub fn draw_run<T: B2DT<Pixel = u16>>(
+        &self,
+        font: &Font,
+        dest: &mut B2D<T>,
+        run: &GlyphRun,
+        offset: (i32, i32),
+    ) {
+        let style = self.style.with_color(run.color);
+        for (c, (x, y)) in &run.glyphs {
+            blit_char(font, dest, *c, (offset.0 + x, offset.1 + y), style);
+        }
+    }
+}
+
+fn styled_width(font: &Font, chars: &[StyledChar]) -> i32 {
+    chars.iter().map(|(c, _)| font.advance(*c)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer2d::{markup::COLOR_CODES, text::Glyph};
+
+    // 'a' is 3 wide and the space 2, lines are 4 high
+    fn font() -> Font {
+        let a = Glyph {
+            pixels: vec![(0, 0, 1)],
+            bearing: (0, 0),
+            advance: 3,
+        };
+        Font::from_glyphs(vec![a, Glyph::empty(2)], [('a', 0), (' ', 1)], 4)
+    }
+
+    fn layout(text: &str, width: i32, rules: LayoutRules) -> TextLayout {
+        TextLayout::new(&font(), text, width, rules, TextStyle::default())
+    }
+
+    // Pen x of every 'a', per line
+    fn columns(layout: &TextLayout) -> Vec<Vec<i32>> {
+        let mut lines = vec![vec![]; layout.line_count];
+        for run in &layout.runs {
+            for (c, (x, y)) in &run.glyphs {
+                assert_eq!(*y, run.line as i32 * layout.line_height);
+                if *c == 'a' {
+                    lines[run.line].push(*x);
+                }
+            }
+        }
+        lines
+    }
+
+    fn aligned(align: Align) -> LayoutRules {
+        LayoutRules {
+            align,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn wraps_between_words() {
+        let layout = layout("aa a aaa", 10, LayoutRules::default());
+        assert_eq!(columns(&layout), [vec![0, 3], vec![0], vec![0, 3, 6]]);
+        assert_eq!(layout.offset, (0, 0));
+        assert_eq!(layout.size, (9, 12));
+    }
+
+    #[test]
+    fn breaks_long_words_and_char_wrap_between_characters() {
+        let layout_word = layout("aaaa", 7, LayoutRules::default());
+        let layout_char = layout(
+            "aa aa",
+            7,
+            LayoutRules {
+                wrap: Wrap::Char,
+                ..Default::default()
+            },
+        );
+        assert_eq!(columns(&layout_word), [vec![0, 3], vec![0, 3]]);
+        assert_eq!(columns(&layout_char), [vec![0, 3], vec![0, 3]]);
+    }
+
+    #[test]
+    fn no_wrap_only_breaks_at_newlines() {
+        let rules = LayoutRules {
+            wrap: Wrap::None,
+            ..Default::default()
+        };
+        let layout = layout("aa aa aa\na", 5, rules);
+        assert_eq!(columns(&layout), [vec![0, 3, 8, 11, 16, 19], vec![0]]);
+        assert_eq!(layout.size, (22, 8));
+    }
+
+    #[test]
+    fn a_width_narrower_than_a_glyph_still_makes_progress() {
+        let layout = layout("aaa", 1, LayoutRules::default());
+        assert_eq!(columns(&layout), [vec![0], vec![0], vec![0]]);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let center = layout("aa a aaa", 10, aligned(Align::Center));
+        assert_eq!(columns(&center), [vec![2, 5], vec![3], vec![0, 3, 6]]);
+
+        let right = layout("aa a aaa", 10, aligned(Align::Right));
+        assert_eq!(columns(&right), [vec![4, 7], vec![7], vec![1, 4, 7]]);
+        assert_eq!(right.offset, (1, 0));
+        assert_eq!(right.size, (9, 12));
+    }
+
+    #[test]
+    fn justify_spreads_wrapped_lines_only() {
+        let layout = layout("a a a a a a a\na a", 15, aligned(Align::Justify));
+        assert_eq!(
+            columns(&layout),
+            [vec![0, 6, 12], vec![0, 6, 12], vec![0], vec![0, 5]]
+        );
+    }
+
+    #[test]
+    fn indents_wrapped_lines() {
+        let rules = LayoutRules {
+            indent: 2,
+            ..Default::default()
+        };
+        let layout = layout("aa a\na", 6, rules);
+        assert_eq!(columns(&layout), [vec![0, 3], vec![2], vec![0]]);
+    }
+
+    #[test]
+    fn splits_runs_by_color() {
+        let style = TextStyle::default().with_markup();
+        let layout = TextLayout::new(&font(), "a^1a a", 20, LayoutRules::default(), style);
+        let runs = Vec::from_iter(layout.runs.iter().map(|run| (run.color, run.glyphs.len())));
+        assert_eq!(runs, [(None, 1), (Some(COLOR_CODES[1]), 3)]);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::utils::color_from_tuple;
+
+// ^0 to ^9, in the order Quake 3 uses them
+pub const COLOR_CODES: [u16; 10] = [
+    color_from_tuple((0, 0, 0)),
+    color_from_tuple((31, 4, 4)),
+    color_from_tuple((4, 31, 4)),
+    color_from_tuple((31, 31, 4)),
+    color_from_tuple((6, 10, 31)),
+    color_from_tuple((4, 31, 31)),
+    color_from_tuple((31, 4, 31)),
+    color_from_tuple((31, 31, 31)),
+    color_from_tuple((31, 18, 2)),
+    color_from_tuple((18, 18, 18)),
+];
+
+const NAMED_COLORS: [(&str, usize); 11] = [
+    ("black", 0),
+    ("red", 1),
+    ("green", 2),
+    ("yellow", 3),
+    ("blue", 4),
+    ("cyan", 5),
+    ("magenta", 6),
+    ("white", 7),
+    ("orange", 8),
+    ("grey", 9),
+    ("gray", 9),
+];
+
+// A character and the color it is drawn in, None keeps the glyph's own colors
+pub type StyledChar = (char, Option<u16>);
+
+// ^0 to ^9 switch the current color, {name}...{/} nest, ^^ and {{ are escapes
+// Anything that doesn't form a known code is kept as text
+pub fn parse_markup(text: &str, color: Option<u16>) -> Vec<StyledChar> {
+    let mut styled = Vec::with_capacity(text.len());
+    let mut stack = vec![color];
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let top = stack.len() - 1;
+
+        match c {
+            '^' => match rest.chars().next() {
+                Some(digit @ '0'..='9') => {
+                    stack[top] = Some(COLOR_CODES[digit as usize - '0' as usize]);
+                    rest = &rest[1..];
+                    continue;
+                }
+                Some('^') => rest = &rest[1..],
+                _ => {}
+            },
+            '{' => {
+                if let Some(escaped) = rest.strip_prefix('{') {
+                    rest = escaped;
+                } else if let Some((tag, after)) = rest.split_once('}') {
+                    if tag == "/" {
+                        if top > 0 {
+                            stack.pop();
+                        }
+                        rest = after;
+                        continue;
+                    }
+                    if let Some(color) = named_color(tag) {
+                        stack.push(Some(color));
+                        rest = after;
+                        continue;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        styled.push((c, stack[top]));
+    }
+
+    styled
+}
+
+fn named_color(name: &str) -> Option<u16> {
+    NAMED_COLORS
+        .iter()
+        .find(|(color_name, _)| color_name.eq_ignore_ascii_case(name))
+        .map(|(_, code)| COLOR_CODES[*code])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(styled: &[StyledChar]) -> String {
+        String::from_iter(styled.iter().map(|(c, _)| c))
+    }
+
+    #[test]
+    fn color_codes_switch_the_color() {
+        let styled = parse_markup("a^1b^9c", None);
+        assert_eq!(text(&styled), "abc");
+        assert_eq!(
+            Vec::from_iter(styled.iter().map(|(_, color)| *color)),
+            [None, Some(COLOR_CODES[1]), Some(COLOR_CODES[9])]
+        );
+    }
+
+    #[test]
+    fn tags_nest_and_restore_the_outer_color() {
+        let white = Some(COLOR_CODES[7]);
+        let styled = parse_markup("a{RED}b{green}c{/}d{/}e{/}f", white);
+        assert_eq!(text(&styled), "abcdef");
+        assert_eq!(
+            Vec::from_iter(styled.iter().map(|(_, color)| *color)),
+            [
+                white,
+                Some(COLOR_CODES[1]),
+                Some(COLOR_CODES[2]),
+                Some(COLOR_CODES[1]),
+                white,
+                white
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_and_unknown_codes_stay_text() {
+        let styled = parse_markup("^^1{{red}{nope}^x{unclosed^", None);
+        assert_eq!(text(&styled), "^1{red}{nope}^x{unclosed^");
+        assert!(styled.iter().all(|(_, color)| color.is_none()));
+    }
+
+    #[test]
+    fn keeps_multibyte_characters() {
+        let styled = parse_markup("é^2ü{blue}☺", None);
+        assert_eq!(text(&styled), "éü☺");
+        assert_eq!(styled[2].1, Some(COLOR_CODES[4]));
+    }
+}
@@ -0,0 +1,87 @@
+use super::{pixel::Pixel, B2DO, MASK_COLOR};
+
+// Level 0 is the texture itself, every following level halves it down to 1x1
+#[derive(Clone)]
+pub struct MipChain<P: Pixel = u16> {
+    pub levels: Vec<B2DO<P>>,
+}
+
+impl MipChain {
+    pub fn new(texture: B2DO) -> Self {
+        let mut levels = vec![texture];
+        while let Some(last) = levels.last() {
+            if last.width <= 1 && last.height <= 1 {
+                break;
+            }
+            let next = downsample(last);
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+}
+
+impl<P: Pixel> MipChain<P> {
+    pub fn base(&self) -> &B2DO<P> {
+        &self.levels[0]
+    }
+
+    // Rounds to the nearest level, anything below 0 is magnification and uses the base
+    pub fn level(&self, lod: f32) -> (usize, &B2DO<P>) {
+        let index = ((lod + 0.5).max(0.0) as usize).min(self.levels.len() - 1);
+        (index, &self.levels[index])
+    }
+
+    // Converts every level on its own, filtering already happened in the source format
+    pub fn map_pixels<Q: Pixel>(&self, mut f: impl FnMut(P) -> Q) -> MipChain<Q> {
+        MipChain {
+            levels: Vec::from_iter(self.levels.iter().map(|level| level.map_pixels(&mut f))),
+        }
+    }
+}
+
+// 2x2 box filter, the last column and row of odd sizes take in the leftover texels as well
+fn downsample(texture: &B2DO) -> B2DO {
+    let mut result = B2DO::new((texture.width / 2).max(1), (texture.height / 2).max(1));
+    let footprint = |i: i32, size: i32, source_size: i32| match i == size - 1 {
+        true => i * 2..source_size,
+        false => i * 2..i * 2 + 2,
+    };
+
+    for y in 0..result.height {
+        for x in 0..result.width {
+            let mut channels = [0u32; 3];
+            let mut opaque = 0;
+            let mut total = 0;
+            for source_y in footprint(y, result.height, texture.height) {
+                for source_x in footprint(x, result.width, texture.width) {
+                    total += 1;
+                    let color = texture.get_color(source_x as usize, source_y as usize);
+                    if color == MASK_COLOR {
+                        continue;
+                    }
+
+                    for (i, channel) in channels.iter_mut().enumerate() {
+                        *channel += (color as u32 >> (10 - i * 5)) & 0x1F;
+                    }
+                    opaque += 1;
+                }
+            }
+
+            // Cutouts stay cut out when most of the block is transparent
+            let color = match opaque == 0 || opaque * 2 < total {
+                true => MASK_COLOR,
+                false => {
+                    let [r, g, b] = channels.map(|channel| (channel / opaque) as u16);
+                    match (r << 10) | (g << 5) | b {
+                        MASK_COLOR => MASK_COLOR - 1,
+                        color => color,
+                    }
+                }
+            };
+            result.set_color(x, y, color);
+        }
+    }
+
+    result
+}
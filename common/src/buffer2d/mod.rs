@@ -1,123 +1,249 @@
+pub mod blend;
+pub mod dither;
+pub mod layout;
+pub mod markup;
+pub mod mip_chain;
+pub mod pixel;
+pub mod primitives;
+pub mod sampler;
 pub mod text;
 pub mod virtual_window;
 
-use std::{
-    mem,
-    ops::{Deref, DerefMut},
-};
+use std::ops::{Deref, DerefMut};
 
-use cgmath::Vector3;
+use cgmath::{BaseFloat, Vector3};
 
 use crate::utils::calculate_index;
 
+use self::{
+    blend::{blend, blend_slice, BlendMode},
+    pixel::Pixel,
+    primitives::walk_line,
+    sampler::Sampler,
+};
+
 pub const MASK_COLOR: u16 = 1023;
 
-pub trait B2DT: Deref<Target = [u16]> + DerefMut<Target = [u16]> {}
-impl<T> B2DT for T where T: Deref<Target = [u16]> + DerefMut<Target = [u16]> {}
+pub trait B2DT: Deref<Target = [Self::Pixel]> + DerefMut<Target = [Self::Pixel]> {
+    type Pixel: Pixel;
+}
 
-pub type B2DO = B2D<Vec<u16>>;
-pub type B2DS<'a> = B2D<&'a mut [u16]>;
+impl<P: Pixel> B2DT for Vec<P> {
+    type Pixel = P;
+}
+
+impl<P: Pixel> B2DT for &mut [P] {
+    type Pixel = P;
+}
 
+// RGB555 unless stated otherwise
+pub type B2DO<P = u16> = B2D<Vec<P>>;
+pub type B2DS<'a, P = u16> = B2D<&'a mut [P]>;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+}
+
+impl Flip {
+    pub const NONE: Flip = Flip {
+        horizontal: false,
+        vertical: false,
+    };
+    pub const HORIZONTAL: Flip = Flip {
+        horizontal: true,
+        vertical: false,
+    };
+    pub const VERTICAL: Flip = Flip {
+        horizontal: false,
+        vertical: true,
+    };
+    pub const BOTH: Flip = Flip {
+        horizontal: true,
+        vertical: true,
+    };
+}
+
+// Clockwise
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rotation {
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+// Rows are `stride` pixels apart, which only differs from `width` for views into larger buffers
+#[derive(Clone)]
 pub struct B2D<T: B2DT> {
     pub width: i32,
     pub height: i32,
+    pub stride: i32,
     pub bitmap: T,
 }
 
-impl B2DO {
+impl<P: Pixel> B2DO<P> {
     pub fn new(width: i32, height: i32) -> Self {
         Self {
             width: width,
             height: height,
-            bitmap: vec![0; (width * height) as usize],
+            stride: width,
+            bitmap: vec![P::default(); (width * height) as usize],
         }
     }
 
     pub fn resize(&mut self, width: i32, height: i32) {
         self.width = width;
         self.height = height;
-        self.bitmap.resize((width * height) as usize, 0);
+        self.stride = width;
+        self.bitmap.resize((width * height) as usize, P::default());
     }
 
-    pub fn as_b2ds(&mut self) -> B2DS {
+    pub fn as_b2ds(&mut self) -> B2DS<'_, P> {
         B2DS {
             width: self.width,
             height: self.height,
+            stride: self.stride,
             bitmap: &mut self.bitmap,
         }
     }
 }
 
 impl<T: B2DT> B2D<T> {
-    pub fn get_color(&self, x: usize, y: usize) -> u16 {
-        self.bitmap[(y * self.width as usize) + x]
-    }
+    // The rectangle is clipped to the buffer, drawing into the view lands in the parent
+    pub fn sub_view(&mut self, x: i32, y: i32, width: i32, height: i32) -> B2DS<'_, T::Pixel> {
+        let x0 = x.clamp(0, self.width);
+        let y0 = y.clamp(0, self.height);
+        let x1 = (x + width).clamp(x0, self.width);
+        let y1 = (y + height).clamp(y0, self.height);
+
+        let (start, end) = match x1 > x0 && y1 > y0 {
+            true => (
+                calculate_index(x0, y0, self.stride),
+                calculate_index(x1, y1 - 1, self.stride),
+            ),
+            false => (0, 0),
+        };
 
-    pub fn sample(&self, u: f32, v: f32) -> u16 {
-        let x = (u * (self.width as f32 - 1.0)) as usize;
-        let y = (v * (self.height as f32 - 1.0)) as usize;
+        B2DS {
+            width: x1 - x0,
+            height: y1 - y0,
+            stride: self.stride,
+            bitmap: &mut self.bitmap[start..end],
+        }
+    }
 
-        self.get_color(x, y)
+    pub fn get_color(&self, x: usize, y: usize) -> T::Pixel {
+        self.bitmap[(y * self.stride as usize) + x]
     }
 
-    pub fn set_color(&mut self, x: i32, y: i32, c: u16) {
-        self.set_color_by_index(calculate_index(x, y, self.width), c);
+    pub fn set_color(&mut self, x: i32, y: i32, c: T::Pixel) {
+        self.set_color_by_index(calculate_index(x, y, self.stride), c);
     }
 
-    pub fn set_color_by_index(&mut self, index: usize, c: u16) {
+    pub fn set_color_by_index(&mut self, index: usize, c: T::Pixel) {
         self.bitmap[index] = c;
     }
 
-    pub fn draw_line_2d(&mut self, p0: Vector3<f32>, p1: Vector3<f32>, c: u16) {
-        let mut x0 = (p0.x as i32).clamp(0, self.width - 1);
-        let mut x1 = (p1.x as i32).clamp(0, self.width - 1);
-        let mut y0 = (p0.y as i32).clamp(0, self.height - 1);
-        let mut y1 = (p1.y as i32).clamp(0, self.height - 1);
-        let mut z0 = p0.z;
-        let mut z1 = p1.z;
-
-        let mut steep = false;
-        if (x0 - x1).abs() < (y0 - y1).abs() {
-            mem::swap(&mut x0, &mut y0);
-            mem::swap(&mut x1, &mut y1);
-            steep = true;
-        }
-        if x0 > x1 {
-            mem::swap(&mut x0, &mut x1);
-            mem::swap(&mut y0, &mut y1);
-            mem::swap(&mut z0, &mut z1);
-        }
-        let dx = x1 - x0;
-        let dy = y1 - y0;
-        let dz = if x1 == x0 {
-            0.0
-        } else {
-            (z1 - z0) as f32 / (x1 - x0) as f32
+    pub fn draw_line_2d(&mut self, p0: Vector3<f32>, p1: Vector3<f32>, c: T::Pixel) {
+        self.walk_line_2d(p0, p1, |buffer, x, y, _| buffer.set_color(x, y, c));
+    }
+
+    // Only writes where z is closer than the z-buffer, which is laid out like the buffer without stride
+    pub fn draw_line_2d_depth(
+        &mut self,
+        p0: Vector3<f32>,
+        p1: Vector3<f32>,
+        c: T::Pixel,
+        z_buffer: &[f32],
+    ) {
+        let width = self.width;
+        self.walk_line_2d(p0, p1, |buffer, x, y, z| {
+            if z < z_buffer[calculate_index(x, y, width)] {
+                buffer.set_color(x, y, c);
+            }
+        });
+    }
+
+    // Clips to the buffer first so lines leaving it keep their slope, z is interpolated along
+    fn walk_line_2d(
+        &mut self,
+        p0: Vector3<f32>,
+        p1: Vector3<f32>,
+        mut plot: impl FnMut(&mut Self, i32, i32, f32),
+    ) {
+        let Some((p0, p1)) = self.clip_line_2d(p0, p1) else {
+            return;
         };
 
-        let mut error = 0;
-        let d_error = 2 * dy.abs();
+        let (x0, y0) = (p0.x as i32, p0.y as i32);
+        let (x1, y1) = (p1.x as i32, p1.y as i32);
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).max(1) as f32;
+        walk_line((x0, y0), (x1, y1), |x, y, step| {
+            plot(self, x, y, p0.z + (p1.z - p0.z) * step as f32 / steps)
+        });
+    }
 
-        let mut y = y0;
-        let mut z = z0;
+    // Liang-Barsky against the pixel centers of the buffer, f64 keeps far off endpoints exact
+    pub fn clip_line_2d<S: BaseFloat>(
+        &self,
+        p0: Vector3<S>,
+        p1: Vector3<S>,
+    ) -> Option<(Vector3<S>, Vector3<S>)> {
+        if self.width <= 0 || self.height <= 0 {
+            return None;
+        }
 
-        for x in x0..x1 {
-            z += dz;
-            if steep {
-                self.set_color(y, x, c);
-            } else {
-                self.set_color(x, y, c);
+        let (max_x, max_y) = (S::from(self.width - 1)?, S::from(self.height - 1)?);
+        let delta = p1 - p0;
+        let (mut t0, mut t1) = (S::zero(), S::one());
+
+        for (p, q) in [
+            (-delta.x, p0.x),
+            (delta.x, max_x - p0.x),
+            (-delta.y, p0.y),
+            (delta.y, max_y - p0.y),
+        ] {
+            if p == S::zero() {
+                // Parallel to this edge and outside of it
+                if q < S::zero() {
+                    return None;
+                }
+                continue;
             }
 
-            error += d_error;
-            if error > dx {
-                y += if y1 > y0 { 1 } else { -1 };
-                error -= 2 * dx;
+            let t = q / p;
+            if p < S::zero() {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+            if t0 > t1 {
+                return None;
             }
         }
+
+        Some((p0 + delta * t0, p0 + delta * t1))
+    }
+
+    pub fn fill(&mut self, color: T::Pixel) {
+        self.blit_fill((0, 0), (self.width, self.height), color);
+    }
+
+    // Shifts every row up, clearing the rows that come in at the bottom
+    pub fn scroll_up(&mut self, rows: i32, color: T::Pixel) {
+        let rows = rows.clamp(0, self.height);
+        for y in 0..self.height - rows {
+            let source = calculate_index(0, y + rows, self.stride);
+            let dest = calculate_index(0, y, self.stride);
+            self.bitmap
+                .copy_within(source..source + self.width as usize, dest);
+        }
+        self.blit_fill((0, self.height - rows), (self.width, rows), color);
     }
 
-    pub fn blit_fill(&mut self, mut offset: (i32, i32), mut size: (i32, i32), color: u16) {
+    pub fn blit_fill(&mut self, mut offset: (i32, i32), mut size: (i32, i32), color: T::Pixel) {
         offset = (
             offset.0.clamp(0, self.width),
             offset.1.clamp(0, self.height),
@@ -128,7 +254,7 @@ impl<T: B2DT> B2D<T> {
         );
 
         for y in offset.1..offset.1 + size.1 {
-            let index = calculate_index(offset.0, y, self.width) as usize;
+            let index = calculate_index(offset.0, y, self.stride) as usize;
             self.bitmap[index..index + size.0 as usize].fill(color);
             // self.bitmap[index..index + size.0 as usize]
             //     .iter_mut()
@@ -136,38 +262,27 @@ impl<T: B2DT> B2D<T> {
         }
     }
 
-    pub fn blit_buffer_full<A: B2DT>(&mut self, buffer: &B2D<A>, offset: (i32, i32)) {
-        self.blit_full(
-            &buffer.bitmap,
-            (buffer.width as i32, buffer.height as i32),
-            offset,
-        )
-    }
-
-    pub fn blit_buffer_full_masked<A: B2DT>(&mut self, buffer: &B2D<A>, offset: (i32, i32)) {
-        self.blit_full_masked(
+    pub fn blit_buffer_full<A: B2DT<Pixel = T::Pixel>>(
+        &mut self,
+        buffer: &B2D<A>,
+        offset: (i32, i32),
+    ) {
+        self.blit_region_copy(
             &buffer.bitmap,
-            (buffer.width as i32, buffer.height as i32),
+            (0, 0),
+            (buffer.width, buffer.height),
+            buffer.stride,
             offset,
         )
     }
 
-    pub fn blit_full(&mut self, source: &[u16], source_size: (i32, i32), offset: (i32, i32)) {
+    pub fn blit_full(&mut self, source: &[T::Pixel], source_size: (i32, i32), offset: (i32, i32)) {
         self.blit_region_copy(source, (0, 0), source_size, source_size.0, offset)
     }
 
-    pub fn blit_full_masked(
-        &mut self,
-        source: &[u16],
-        source_size: (i32, i32),
-        offset: (i32, i32),
-    ) {
-        self.blit_region_masked(source, (0, 0), source_size, source_size.0, offset)
-    }
-
     pub fn blit_region_copy(
         &mut self,
-        source: &[u16],
+        source: &[T::Pixel],
         source_offset: (i32, i32),
         image_length: (i32, i32),
         source_width: i32,
@@ -185,6 +300,81 @@ impl<T: B2DT> B2D<T> {
         )
     }
 
+    pub fn blit_region(
+        &mut self,
+        source: &[T::Pixel],
+        source_offset: (i32, i32),
+        mut image_length: (i32, i32),
+        source_width: i32,
+        offset: (i32, i32),
+        method: impl Fn(&mut [T::Pixel], &[T::Pixel]),
+    ) {
+        let mut source_offset_x = source_offset.0;
+        if offset.0 < 0 {
+            source_offset_x -= offset.0;
+            image_length.0 += offset.0;
+        }
+        image_length.0 = image_length.0.min(self.width - offset.0);
+        if image_length.0 <= 0 {
+            return;
+        }
+
+        let mut source_offset_y = source_offset.1;
+        if offset.1 < 0 {
+            source_offset_y -= offset.1;
+            image_length.1 += offset.1;
+        }
+        image_length.1 = image_length.1.min(self.height - offset.1);
+        if image_length.1 <= 0 {
+            return;
+        }
+
+        let slice_length = image_length.0 as usize;
+
+        let dest_offset_x = offset.0.max(0);
+        let dest_offset_y = offset.1.max(0);
+
+        for y in 0..image_length.1 {
+            let dest_index = calculate_index(dest_offset_x, y + dest_offset_y, self.stride);
+            let source_index = calculate_index(source_offset_x, y + source_offset_y, source_width);
+
+            method(
+                &mut self.bitmap[dest_index..dest_index + slice_length],
+                &source[source_index..source_index + slice_length],
+            );
+        }
+    }
+}
+
+// Masking and blending are defined for RGB555 only
+impl<T: B2DT<Pixel = u16>> B2D<T> {
+    pub fn sample(&self, u: f32, v: f32, sampler: Sampler) -> u16 {
+        sampler.sample(self, u, v)
+    }
+
+    pub fn blit_buffer_full_masked<A: B2DT<Pixel = u16>>(
+        &mut self,
+        buffer: &B2D<A>,
+        offset: (i32, i32),
+    ) {
+        self.blit_region_masked(
+            &buffer.bitmap,
+            (0, 0),
+            (buffer.width, buffer.height),
+            buffer.stride,
+            offset,
+        )
+    }
+
+    pub fn blit_full_masked(
+        &mut self,
+        source: &[u16],
+        source_size: (i32, i32),
+        offset: (i32, i32),
+    ) {
+        self.blit_region_masked(source, (0, 0), source_size, source_size.0, offset)
+    }
+
     pub fn blit_region_masked(
         &mut self,
         source: &[u16],
@@ -209,48 +399,189 @@ impl<T: B2DT> B2D<T> {
         )
     }
 
-    pub fn blit_region(
+    pub fn blit_region_blended(
         &mut self,
         source: &[u16],
         source_offset: (i32, i32),
-        mut image_length: (i32, i32),
+        image_length: (i32, i32),
         source_width: i32,
         offset: (i32, i32),
-        method: fn(&mut [u16], &[u16]),
+        mode: BlendMode,
     ) {
-        let mut source_offset_x = source_offset.0;
-        if offset.0 < 0 {
-            source_offset_x -= offset.0;
-            image_length.0 += offset.0;
-        }
-        image_length.0 = image_length.0.min(self.width - offset.0);
-        if image_length.0 <= 0 {
+        self.blit_region(
+            source,
+            source_offset,
+            image_length,
+            source_width,
+            offset,
+            |dest, source| blend_slice(dest, source, mode),
+        )
+    }
+
+    pub fn blit_buffer_blended<A: B2DT<Pixel = u16>>(
+        &mut self,
+        buffer: &B2D<A>,
+        offset: (i32, i32),
+        mode: BlendMode,
+    ) {
+        self.blit_region_blended(
+            &buffer.bitmap,
+            (0, 0),
+            (buffer.width, buffer.height),
+            buffer.stride,
+            offset,
+            mode,
+        )
+    }
+
+    // Blends a solid color over the rect, e.g. to dim or flash part of the screen
+    pub fn blit_fill_blended(
+        &mut self,
+        offset: (i32, i32),
+        size: (i32, i32),
+        color: u16,
+        mode: BlendMode,
+    ) {
+        let start_x = offset.0.max(0);
+        let start_y = offset.1.max(0);
+        let end_x = (offset.0 + size.0).min(self.width);
+        let end_y = (offset.1 + size.1).min(self.height);
+        if start_x >= end_x {
             return;
         }
 
-        let mut source_offset_y = source_offset.1;
-        if offset.1 < 0 {
-            source_offset_y -= offset.1;
-            image_length.1 += offset.1;
+        for y in start_y..end_y {
+            let index = calculate_index(start_x, y, self.stride);
+            self.bitmap[index..index + (end_x - start_x) as usize]
+                .iter_mut()
+                .for_each(|dest| *dest = blend(*dest, color, mode));
         }
-        image_length.1 = image_length.1.min(self.height - offset.1);
-        if image_length.1 <= 0 {
+    }
+
+    // Nearest neighbor, the source is stretched over the destination rect (x, y, width, height)
+    pub fn blit_scaled<A: B2DT<Pixel = u16>>(
+        &mut self,
+        source: &B2D<A>,
+        rect: (i32, i32, i32, i32),
+        flip: Flip,
+        mode: BlendMode,
+    ) {
+        if rect.2 <= 0 || rect.3 <= 0 {
             return;
         }
 
-        let slice_length = image_length.0 as usize;
+        self.blit_mapped(source, rect, mode, |x, y| {
+            let x = flip_coordinate(x, rect.2, flip.horizontal);
+            let y = flip_coordinate(y, rect.3, flip.vertical);
+            (x * source.width / rect.2, y * source.height / rect.3)
+        });
+    }
 
-        let dest_offset_x = offset.0.max(0);
-        let dest_offset_y = offset.1.max(0);
+    pub fn blit_flipped<A: B2DT<Pixel = u16>>(
+        &mut self,
+        source: &B2D<A>,
+        offset: (i32, i32),
+        flip: Flip,
+        mode: BlendMode,
+    ) {
+        self.blit_scaled(
+            source,
+            (offset.0, offset.1, source.width, source.height),
+            flip,
+            mode,
+        );
+    }
 
-        for y in 0..image_length.1 {
-            let dest_index = calculate_index(dest_offset_x, y + dest_offset_y, self.width);
-            let source_index = calculate_index(source_offset_x, y + source_offset_y, source_width);
+    // Flipping happens after rotating, so it mirrors what ends up on screen
+    pub fn blit_rotated_90<A: B2DT<Pixel = u16>>(
+        &mut self,
+        source: &B2D<A>,
+        offset: (i32, i32),
+        rotation: Rotation,
+        flip: Flip,
+        mode: BlendMode,
+    ) {
+        let (width, height) = match rotation {
+            Rotation::R0 | Rotation::R180 => (source.width, source.height),
+            Rotation::R90 | Rotation::R270 => (source.height, source.width),
+        };
 
-            method(
-                &mut self.bitmap[dest_index..dest_index + slice_length],
-                &source[source_index..source_index + slice_length],
-            );
+        self.blit_mapped(source, (offset.0, offset.1, width, height), mode, |x, y| {
+            let x = flip_coordinate(x, width, flip.horizontal);
+            let y = flip_coordinate(y, height, flip.vertical);
+            match rotation {
+                Rotation::R0 => (x, y),
+                Rotation::R90 => (y, source.height - 1 - x),
+                Rotation::R180 => (source.width - 1 - x, source.height - 1 - y),
+                Rotation::R270 => (source.width - 1 - y, x),
+            }
+        });
+    }
+
+    // Rotates clockwise by `angle` radians around the source center, which lands on `center`
+    // Flipping mirrors the rotated result around that center, the same as blit_rotated_90
+    pub fn blit_rotated<A: B2DT<Pixel = u16>>(
+        &mut self,
+        source: &B2D<A>,
+        center: (i32, i32),
+        angle: f32,
+        flip: Flip,
+        mode: BlendMode,
+    ) {
+        let radius = ((source.width * source.width + source.height * source.height) as f32)
+            .sqrt()
+            .ceil() as i32
+            / 2
+            + 1;
+        let rect = (center.0 - radius, center.1 - radius, radius * 2, radius * 2);
+
+        let (sin, cos) = angle.sin_cos();
+        let half_width = source.width as f32 / 2.0;
+        let half_height = source.height as f32 / 2.0;
+
+        self.blit_mapped(source, rect, mode, |x, y| {
+            let x = flip_coordinate(x, rect.2, flip.horizontal);
+            let y = flip_coordinate(y, rect.3, flip.vertical);
+            let x = (x - radius) as f32 + 0.5;
+            let y = (y - radius) as f32 + 0.5;
+            (
+                (x * cos + y * sin + half_width).floor() as i32,
+                (-x * sin + y * cos + half_height).floor() as i32,
+            )
+        });
+    }
+
+    // Maps every visible destination pixel of rect back to a source pixel
+    fn blit_mapped<A: B2DT<Pixel = u16>>(
+        &mut self,
+        source: &B2D<A>,
+        rect: (i32, i32, i32, i32),
+        mode: BlendMode,
+        map: impl Fn(i32, i32) -> (i32, i32),
+    ) {
+        let start_x = rect.0.max(0);
+        let start_y = rect.1.max(0);
+        let end_x = (rect.0 + rect.2).min(self.width);
+        let end_y = (rect.1 + rect.3).min(self.height);
+
+        for dest_y in start_y..end_y {
+            for dest_x in start_x..end_x {
+                let (x, y) = map(dest_x - rect.0, dest_y - rect.1);
+                if x < 0 || y < 0 || x >= source.width || y >= source.height {
+                    continue;
+                }
+
+                let index = calculate_index(dest_x, dest_y, self.stride);
+                let color = source.get_color(x as usize, y as usize);
+                self.bitmap[index] = blend(self.bitmap[index], color, mode);
+            }
         }
     }
 }
+
+fn flip_coordinate(coordinate: i32, length: i32, flip: bool) -> i32 {
+    match flip {
+        true => length - 1 - coordinate,
+        false => coordinate,
+    }
+}
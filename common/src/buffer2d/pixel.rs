@@ -0,0 +1,176 @@
+use std::fmt::Debug;
+
+use crate::image::ALPHA_THRESHOLD;
+
+use super::{B2D, B2DO, B2DT, MASK_COLOR};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb555,
+    Rgb565,
+    Rgba8888,
+    Indexed8,
+}
+
+pub trait Pixel: Copy + Default + PartialEq + Debug {
+    const FORMAT: PixelFormat;
+}
+
+// Formats that store their color directly, conversions go through 8 bit RGBA
+pub trait DirectPixel: Pixel {
+    fn from_rgba8(rgba: [u8; 4]) -> Self;
+    fn to_rgba8(self) -> [u8; 4];
+
+    fn convert<P: DirectPixel>(self) -> P {
+        P::from_rgba8(self.to_rgba8())
+    }
+}
+
+// Plain u16 is RGB555, MASK_COLOR doubles as its only transparent value
+impl Pixel for u16 {
+    const FORMAT: PixelFormat = PixelFormat::Rgb555;
+}
+
+impl DirectPixel for u16 {
+    fn from_rgba8([r, g, b, a]: [u8; 4]) -> Self {
+        if a < ALPHA_THRESHOLD {
+            return MASK_COLOR;
+        }
+
+        match ((r as u16 >> 3) << 10) | ((g as u16 >> 3) << 5) | (b as u16 >> 3) {
+            MASK_COLOR => MASK_COLOR - 1,
+            color => color,
+        }
+    }
+
+    fn to_rgba8(self) -> [u8; 4] {
+        let alpha = match self {
+            MASK_COLOR => 0,
+            _ => 255,
+        };
+        [
+            expand_bits(self >> 10, 5),
+            expand_bits(self >> 5, 5),
+            expand_bits(self, 5),
+            alpha,
+        ]
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Rgb565(pub u16);
+
+impl Pixel for Rgb565 {
+    const FORMAT: PixelFormat = PixelFormat::Rgb565;
+}
+
+impl DirectPixel for Rgb565 {
+    fn from_rgba8([r, g, b, _]: [u8; 4]) -> Self {
+        Self(((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3))
+    }
+
+    fn to_rgba8(self) -> [u8; 4] {
+        [
+            expand_bits(self.0 >> 11, 5),
+            expand_bits(self.0 >> 5, 6),
+            expand_bits(self.0, 5),
+            255,
+        ]
+    }
+}
+
+// Packed with red in the highest byte, the same as SDL's RGBA8888
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Rgba8888(pub u32);
+
+impl Pixel for Rgba8888 {
+    const FORMAT: PixelFormat = PixelFormat::Rgba8888;
+}
+
+impl DirectPixel for Rgba8888 {
+    fn from_rgba8(rgba: [u8; 4]) -> Self {
+        Self(u32::from_be_bytes(rgba))
+    }
+
+    fn to_rgba8(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+}
+
+// An index into a 256 color palette of any direct format
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Indexed8(pub u8);
+
+impl Pixel for Indexed8 {
+    const FORMAT: PixelFormat = PixelFormat::Indexed8;
+}
+
+impl Indexed8 {
+    pub fn resolve<P: Pixel>(self, palette: &[P; 256]) -> P {
+        palette[self.0 as usize]
+    }
+
+    // Nearest palette entry by squared RGB distance, transparent colors only match transparent entries
+    pub fn nearest<P: DirectPixel>(color: P, palette: &[P; 256]) -> Self {
+        let [r, g, b, a] = color.to_rgba8();
+        let transparent = a < ALPHA_THRESHOLD;
+
+        let distance = |entry: &P| {
+            let [er, eg, eb, ea] = entry.to_rgba8();
+            if (ea < ALPHA_THRESHOLD) != transparent {
+                return u32::MAX;
+            }
+            let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+            channel(r, er) + channel(g, eg) + channel(b, eb)
+        };
+
+        let index = palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| distance(entry))
+            .map_or(0, |(index, _)| index);
+        Self(index as u8)
+    }
+}
+
+impl<T: B2DT> B2D<T> {
+    // Copies into a tightly packed buffer, converting every pixel on the way
+    pub fn map_pixels<P: Pixel>(&self, mut f: impl FnMut(T::Pixel) -> P) -> B2DO<P> {
+        let mut bitmap = Vec::with_capacity((self.width * self.height).max(0) as usize);
+        for y in 0..self.height as usize {
+            bitmap.extend((0..self.width as usize).map(|x| f(self.get_color(x, y))));
+        }
+
+        B2DO {
+            width: self.width,
+            height: self.height,
+            stride: self.width,
+            bitmap,
+        }
+    }
+}
+
+impl<T: B2DT> B2D<T>
+where
+    T::Pixel: DirectPixel,
+{
+    pub fn convert<P: DirectPixel>(&self) -> B2DO<P> {
+        self.map_pixels(|color| color.convert())
+    }
+
+    pub fn quantize(&self, palette: &[T::Pixel; 256]) -> B2DO<Indexed8> {
+        self.map_pixels(|color| Indexed8::nearest(color, palette))
+    }
+}
+
+impl<T: B2DT<Pixel = Indexed8>> B2D<T> {
+    pub fn resolve<P: Pixel>(&self, palette: &[P; 256]) -> B2DO<P> {
+        self.map_pixels(|index| index.resolve(palette))
+    }
+}
+
+// Repeats the top bits into the bottom so full intensity maps to 255
+fn expand_bits(value: u16, bits: u32) -> u8 {
+    let value = (value & ((1 << bits) - 1)) as u8;
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
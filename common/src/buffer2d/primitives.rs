@@ -0,0 +1,264 @@
+use cgmath::Vector3;
+
+use super::{B2D, B2DT};
+
+impl<T: B2DT> B2D<T> {
+    // Anything outside of the buffer is silently dropped
+    pub fn set_color_clipped(&mut self, x: i32, y: i32, c: T::Pixel) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.set_color(x, y, c);
+        }
+    }
+
+    pub fn draw_line(&mut self, p0: (i32, i32), p1: (i32, i32), c: T::Pixel) {
+        self.draw_line_2d(
+            Vector3::new(p0.0 as f32, p0.1 as f32, 0.0),
+            Vector3::new(p1.0 as f32, p1.1 as f32, 0.0),
+            c,
+        );
+    }
+
+    // Dash is (on, off) in pixels along the major axis
+    // Only the clipped part is walked, the dash phase starts where the clipped off part ended
+    pub fn draw_line_dashed(
+        &mut self,
+        p0: (i32, i32),
+        p1: (i32, i32),
+        c: T::Pixel,
+        dash: (i32, i32),
+    ) {
+        let Some((start, end)) = self.clip_line_2d(
+            Vector3::new(p0.0 as f64, p0.1 as f64, 0.0),
+            Vector3::new(p1.0 as f64, p1.1 as f64, 0.0),
+        ) else {
+            return;
+        };
+        let start = (start.x.round() as i32, start.y.round() as i32);
+        let end = (end.x.round() as i32, end.y.round() as i32);
+
+        let period = (dash.0 + dash.1).max(1);
+        let skipped = start.0.abs_diff(p0.0).max(start.1.abs_diff(p0.1));
+        let phase = (skipped % period as u32) as i32;
+        walk_line(start, end, |x, y, step| {
+            if (step + phase) % period < dash.0 {
+                self.set_color_clipped(x, y, c);
+            }
+        });
+    }
+
+    pub fn draw_rect(&mut self, offset: (i32, i32), size: (i32, i32), c: T::Pixel) {
+        if size.0 <= 0 || size.1 <= 0 {
+            return;
+        }
+
+        // Corners past the i32 range saturate, they lie outside the buffer either way
+        let (x0, y0) = offset;
+        let (x1, y1) = (x0.saturating_add(size.0 - 1), y0.saturating_add(size.1 - 1));
+        self.draw_span(x0, x1, y0, c);
+        self.draw_span(x0, x1, y1, c);
+
+        // Sides only cover the rows inside the buffer, so huge rects stay cheap
+        let (top, bottom) = (y0.saturating_add(1).max(0), y1.min(self.height));
+        if top >= bottom {
+            return;
+        }
+        for x in [x0, x1] {
+            if x >= 0 && x < self.width {
+                self.blit_fill((x, top), (1, bottom - top), c);
+            }
+        }
+    }
+
+    pub fn draw_circle(&mut self, center: (i32, i32), radius: i32, c: T::Pixel) {
+        self.draw_ellipse(center, (radius, radius), c);
+    }
+
+    pub fn fill_circle(&mut self, center: (i32, i32), radius: i32, c: T::Pixel) {
+        self.fill_ellipse(center, (radius, radius), c);
+    }
+
+    pub fn draw_ellipse(&mut self, center: (i32, i32), radii: (i32, i32), c: T::Pixel) {
+        walk_ellipse(radii, |x, y| {
+            for (sx, sy) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+                self.set_color_clipped(center.0 + sx, center.1 + sy, c);
+            }
+        });
+    }
+
+    pub fn fill_ellipse(&mut self, center: (i32, i32), radii: (i32, i32), c: T::Pixel) {
+        walk_ellipse(radii, |x, y| {
+            self.draw_span(center.0 - x, center.0 + x, center.1 + y, c);
+            self.draw_span(center.0 - x, center.0 + x, center.1 - y, c);
+        });
+    }
+
+    pub fn draw_polygon(&mut self, points: &[(i32, i32)], c: T::Pixel) {
+        for (i, p0) in points.iter().enumerate() {
+            self.draw_line(*p0, points[(i + 1) % points.len()], c);
+        }
+    }
+
+    // Even-odd scanline fill, so concave and self-intersecting outlines work too
+    pub fn fill_polygon(&mut self, points: &[(i32, i32)], c: T::Pixel) {
+        if points.len() < 3 {
+            return;
+        }
+
+        let min_y = points.iter().map(|p| p.1).min().unwrap_or(0).max(0);
+        let max_y = points
+            .iter()
+            .map(|p| p.1)
+            .max()
+            .unwrap_or(0)
+            .min(self.height - 1);
+
+        let mut crossings = Vec::with_capacity(points.len());
+        for y in min_y..=max_y {
+            // Sampling at the pixel center keeps shared vertices from being counted twice
+            let center_y = y as f32 + 0.5;
+
+            crossings.clear();
+            for (i, p0) in points.iter().enumerate() {
+                let p1 = points[(i + 1) % points.len()];
+                let (y0, y1) = (p0.1 as f32, p1.1 as f32);
+                if (y0 <= center_y) != (y1 <= center_y) {
+                    let t = (center_y - y0) / (y1 - y0);
+                    crossings.push(p0.0 as f32 + t * (p1.0 - p0.0) as f32);
+                }
+            }
+            crossings.sort_by(f32::total_cmp);
+
+            for span in crossings.chunks_exact(2) {
+                let x0 = (span[0] - 0.5).ceil() as i32;
+                let x1 = (span[1] - 0.5).ceil() as i32 - 1;
+                self.draw_span(x0, x1, y, c);
+            }
+        }
+    }
+
+    // Scanline flood fill of the 4-connected area sharing the color at (x, y)
+    pub fn flood_fill(&mut self, x: i32, y: i32, c: T::Pixel) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return;
+        }
+
+        let target = self.get_color(x as usize, y as usize);
+        if target == c {
+            return;
+        }
+
+        let matches =
+            |buffer: &Self, x: i32, y: i32| buffer.get_color(x as usize, y as usize) == target;
+
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            if !matches(self, x, y) {
+                continue;
+            }
+
+            let mut x0 = x;
+            while x0 > 0 && matches(self, x0 - 1, y) {
+                x0 -= 1;
+            }
+            let mut x1 = x;
+            while x1 < self.width - 1 && matches(self, x1 + 1, y) {
+                x1 += 1;
+            }
+
+            self.draw_span(x0, x1, y, c);
+
+            // One seed per run in the neighboring rows
+            for ny in [y - 1, y + 1] {
+                if ny < 0 || ny >= self.height {
+                    continue;
+                }
+                let mut in_run = false;
+                for nx in x0..=x1 {
+                    let inside = matches(self, nx, ny);
+                    if inside && !in_run {
+                        stack.push((nx, ny));
+                    }
+                    in_run = inside;
+                }
+            }
+        }
+    }
+
+    // Horizontal run from x0 to x1 inclusive
+    fn draw_span(&mut self, x0: i32, x1: i32, y: i32, c: T::Pixel) {
+        if y < 0 || y >= self.height {
+            return;
+        }
+
+        let (x0, x1) = (x0.min(x1).max(0), x0.max(x1).min(self.width - 1));
+        if x0 <= x1 {
+            self.blit_fill((x0, y), (x1 - x0 + 1, 1), c);
+        }
+    }
+}
+
+// Bresenham, the callback also gets the step count for patterns
+pub(super) fn walk_line(p0: (i32, i32), p1: (i32, i32), mut plot: impl FnMut(i32, i32, i32)) {
+    let dx = (p1.0 - p0.0).abs();
+    let dy = -(p1.1 - p0.1).abs();
+    let sx = if p0.0 < p1.0 { 1 } else { -1 };
+    let sy = if p0.1 < p1.1 { 1 } else { -1 };
+
+    let (mut x, mut y) = p0;
+    let mut error = dx + dy;
+    let mut step = 0;
+    loop {
+        plot(x, y, step);
+        if (x, y) == p1 {
+            break;
+        }
+
+        let error2 = error * 2;
+        if error2 >= dy {
+            error += dy;
+            x += sx;
+        }
+        if error2 <= dx {
+            error += dx;
+            y += sy;
+        }
+        step += 1;
+    }
+}
+
+// Midpoint ellipse, calls back with one quadrant's offsets from the center
+fn walk_ellipse(radii: (i32, i32), mut plot: impl FnMut(i32, i32)) {
+    let (a, b) = (radii.0.max(0) as i64, radii.1.max(0) as i64);
+    let (a2, b2) = (a * a, b * b);
+
+    let (mut x, mut y) = (0, b);
+    let mut dx = 0;
+    let mut dy = 2 * a2 * y;
+    let mut d = b2 - a2 * b + a2 / 4;
+    while dx < dy {
+        plot(x as i32, y as i32);
+        x += 1;
+        dx += 2 * b2;
+        if d < 0 {
+            d += b2 + dx;
+        } else {
+            y -= 1;
+            dy -= 2 * a2;
+            d += b2 + dx - dy;
+        }
+    }
+
+    let mut d = b2 * (2 * x + 1) * (2 * x + 1) / 4 + a2 * (y - 1) * (y - 1) - a2 * b2;
+    while y >= 0 {
+        plot(x as i32, y as i32);
+        y -= 1;
+        dy -= 2 * a2;
+        if d > 0 {
+            d += a2 - dy;
+        } else {
+            x += 1;
+            dx += 2 * b2;
+            d += a2 - dy + dx;
+        }
+    }
+}
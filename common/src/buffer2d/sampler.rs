@@ -0,0 +1,122 @@
+use super::{B2D, B2DT, MASK_COLOR};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,
+    Clamp,
+    MirroredRepeat,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sampler {
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+    pub filter: Filter,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::REPEAT
+    }
+}
+
+impl Sampler {
+    pub const REPEAT: Self = Self::new(AddressMode::Repeat, Filter::Nearest);
+    pub const CLAMP: Self = Self::new(AddressMode::Clamp, Filter::Nearest);
+
+    pub const fn new(address: AddressMode, filter: Filter) -> Self {
+        Self {
+            address_u: address,
+            address_v: address,
+            filter,
+        }
+    }
+
+    pub fn sample<T: B2DT<Pixel = u16>>(&self, texture: &B2D<T>, u: f32, v: f32) -> u16 {
+        if texture.width <= 0 || texture.height <= 0 {
+            return MASK_COLOR;
+        }
+
+        let x = u * texture.width as f32;
+        let y = v * texture.height as f32;
+
+        match self.filter {
+            Filter::Nearest => self.texel(texture, x.floor() as i32, y.floor() as i32),
+            Filter::Bilinear => self.bilinear(texture, x - 0.5, y - 0.5),
+        }
+    }
+
+    // Any format can be addressed, filtering is left out since it needs color math
+    pub fn sample_nearest<T: B2DT>(&self, texture: &B2D<T>, u: f32, v: f32) -> T::Pixel {
+        if texture.width <= 0 || texture.height <= 0 {
+            return T::Pixel::default();
+        }
+
+        let x = (u * texture.width as f32).floor() as i32;
+        let y = (v * texture.height as f32).floor() as i32;
+        self.texel(texture, x, y)
+    }
+
+    fn texel<T: B2DT>(&self, texture: &B2D<T>, x: i32, y: i32) -> T::Pixel {
+        let x = address(self.address_u, x, texture.width);
+        let y = address(self.address_v, y, texture.height);
+        texture.get_color(x as usize, y as usize)
+    }
+
+    // Weights are 8 bit fixed point, masked texels drop out and the rest are renormalized
+    fn bilinear<T: B2DT<Pixel = u16>>(&self, texture: &B2D<T>, x: f32, y: f32) -> u16 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let fx = ((x - x0) * 256.0) as u32;
+        let fy = ((y - y0) * 256.0) as u32;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let texels = [
+            (self.texel(texture, x0, y0), (256 - fx) * (256 - fy)),
+            (self.texel(texture, x0 + 1, y0), fx * (256 - fy)),
+            (self.texel(texture, x0, y0 + 1), (256 - fx) * fy),
+            (self.texel(texture, x0 + 1, y0 + 1), fx * fy),
+        ];
+
+        // Cutout edges follow the nearest texel so sprites keep their outline
+        let nearest = texels
+            .iter()
+            .max_by_key(|(_, weight)| *weight)
+            .map_or(MASK_COLOR, |(color, _)| *color);
+        if nearest == MASK_COLOR {
+            return MASK_COLOR;
+        }
+
+        let mut channels = [0u32; 3];
+        let mut total = 0;
+        for (color, weight) in texels.iter().filter(|(color, _)| *color != MASK_COLOR) {
+            for (i, channel) in channels.iter_mut().enumerate() {
+                *channel += ((*color as u32 >> (10 - i * 5)) & 0x1F) * weight;
+            }
+            total += weight;
+        }
+
+        let [r, g, b] = channels.map(|channel| (channel / total.max(1)) as u16);
+        match (r << 10) | (g << 5) | b {
+            // Filtering must not punch holes into opaque texels
+            MASK_COLOR => MASK_COLOR - 1,
+            color => color,
+        }
+    }
+}
+
+fn address(mode: AddressMode, coordinate: i32, size: i32) -> i32 {
+    match mode {
+        AddressMode::Repeat => coordinate.rem_euclid(size),
+        AddressMode::Clamp => coordinate.clamp(0, size - 1),
+        AddressMode::MirroredRepeat => match coordinate.rem_euclid(size * 2) {
+            mirrored if mirrored >= size => size * 2 - 1 - mirrored,
+            coordinate => coordinate,
+        },
+    }
+}
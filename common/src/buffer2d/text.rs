@@ -1,136 +1,326 @@
-use crate::buffer2d::{B2D, B2DO, B2DT};
+use std::collections::HashMap;
+
+use crate::{
+    buffer2d::{
+        blend::{blend, BlendMode},
+        layout::{LayoutRules, TextLayout},
+        markup::{parse_markup, StyledChar},
+        B2D, B2DO, B2DT,
+    },
+    font::charset::Charset,
+};
 
 const CHARS_FIRST: usize = ' ' as usize;
 const CHARS_LAST: usize = 0x7f;
 const CHARS_COUNT: usize = CHARS_LAST - CHARS_FIRST;
-const CHARS_SPACE: usize = CHARS_FIRST;
-const CHARS_LINE_BREAK: usize = '\n' as usize;
 
-pub struct Glyph(Vec<(i32, i32, u16)>);
+// Tried in order for characters without a glyph
+const FALLBACK_CHARS: [char; 2] = ['\u{fffd}', '?'];
+
+const SHADOW_OFFSET: (i32, i32) = (1, 1);
+const OUTLINE_OFFSETS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TextEffect {
+    #[default]
+    None,
+    Shadow(u16),
+    Outline(u16),
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TextStyle {
+    // Tints the glyph colors, None draws them as the font has them
+    pub color: Option<u16>,
+    pub effect: TextEffect,
+    // Color codes and tags in the text are parsed, see markup::parse_markup
+    pub markup: bool,
+}
+
+impl TextStyle {
+    pub fn tinted(color: u16) -> Self {
+        Self {
+            color: Some(color),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_color(self, color: Option<u16>) -> Self {
+        Self { color, ..self }
+    }
+
+    pub fn with_effect(self, effect: TextEffect) -> Self {
+        Self { effect, ..self }
+    }
+
+    pub fn with_markup(self) -> Self {
+        Self {
+            markup: true,
+            ..self
+        }
+    }
+
+    // Markup characters are dropped here, so they never take up any width
+    pub fn apply(&self, text: &str) -> Vec<StyledChar> {
+        match self.markup {
+            true => parse_markup(text, self.color),
+            false => Vec::from_iter(text.chars().map(|c| (c, self.color))),
+        }
+    }
+}
+
+pub struct Glyph {
+    pub pixels: Vec<(i32, i32, u16)>,
+    // From the pen position to the glyph's pixels, y = 0 is the top of the line
+    pub bearing: (i32, i32),
+    pub advance: i32,
+}
+
+impl Glyph {
+    pub fn empty(advance: i32) -> Self {
+        Self {
+            pixels: vec![],
+            bearing: (0, 0),
+            advance,
+        }
+    }
+}
 
 pub struct Font {
     pub glyphs: Vec<Glyph>,
+    pub glyph_map: HashMap<char, usize>,
+    // Drawn for printable characters the font has no glyph for
+    pub fallback: Option<usize>,
+    // The cell of a monospaced font, otherwise the widest advance and the line height
     pub glyph_size: (i32, i32),
 }
 
 impl Font {
+    // The grid starts at the space character, offsets are in cells
     pub fn new(bitmap: B2DO, glyph_size: (i32, i32), offset_x: i32, offset_y: i32) -> Self {
         let len = bitmap.width / glyph_size.0;
-        let mut glyphs = Vec::with_capacity(CHARS_COUNT as usize);
+        let mut glyphs = Vec::with_capacity(CHARS_COUNT);
         for u in CHARS_FIRST..CHARS_LAST {
             let u = (u - CHARS_FIRST) as i32;
-            let glyph_x_offset = ((u % len) + offset_x) * glyph_size.0;
-            let glyph_y_offset = ((u / len) + offset_y) * glyph_size.1;
-
-            let mut glyph = Glyph(Vec::new());
-
-            for glyph_x in 0..glyph_size.0 {
-                for glyph_y in 0..glyph_size.1 {
-                    let color = bitmap.get_color(
-                        glyph_x_offset as usize + glyph_x as usize,
-                        glyph_y_offset as usize + glyph_y as usize,
-                    );
-                    if color == 0 {
-                        continue;
-                    }
-                    glyph.0.push((glyph_x, glyph_y, color));
-                }
-            }
+            let cell = ((u % len) + offset_x, (u / len) + offset_y);
+            glyphs.push(slice_cell(&bitmap, glyph_size, cell));
+        }
+
+        let glyph_map = (CHARS_FIRST..CHARS_LAST).map(|u| (u as u8 as char, u - CHARS_FIRST));
+        Self::from_glyphs(glyphs, glyph_map, glyph_size.1)
+    }
 
-            glyphs.push(glyph);
+    // Cell n of the grid holds the charset's character n, a whole code page is 16x16 cells
+    pub fn from_grid(bitmap: &B2DO, glyph_size: (i32, i32), charset: Charset) -> Self {
+        let len = (bitmap.width / glyph_size.0).max(1);
+        let count = (len * (bitmap.height / glyph_size.1)).min(256);
+        let glyphs =
+            Vec::from_iter((0..count).map(|u| slice_cell(bitmap, glyph_size, (u % len, u / len))));
+        Self::from_glyphs(glyphs, charset.mapping(), glyph_size.1)
+    }
+
+    // Mappings past the glyphs are dropped, a missing space advances half the line height
+    pub fn from_glyphs(
+        mut glyphs: Vec<Glyph>,
+        glyph_map: impl IntoIterator<Item = (char, usize)>,
+        line_height: i32,
+    ) -> Self {
+        let mut glyph_map = HashMap::from_iter(
+            glyph_map
+                .into_iter()
+                .filter(|(_, index)| *index < glyphs.len()),
+        );
+        glyph_map.entry(' ').or_insert_with(|| {
+            glyphs.push(Glyph::empty(line_height / 2));
+            glyphs.len() - 1
+        });
+
+        let fallback = FALLBACK_CHARS
+            .iter()
+            .find_map(|c| glyph_map.get(c).copied());
+        let width = glyphs.iter().map(|glyph| glyph.advance).max().unwrap_or(0);
+
+        Self {
+            glyphs,
+            glyph_map,
+            fallback,
+            glyph_size: (width, line_height),
         }
+    }
+
+    // Control characters without a glyph of their own are skipped rather than replaced
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        let index = match self.glyph_map.get(&c) {
+            Some(index) => Some(*index),
+            None if c.is_control() => None,
+            None => self.fallback,
+        };
+        index.map(|index| &self.glyphs[index])
+    }
+
+    pub fn advance(&self, c: char) -> i32 {
+        self.glyph(c).map_or(0, |glyph| glyph.advance)
+    }
+
+    pub fn text_width(&self, s: &str) -> i32 {
+        s.chars().map(|c| self.advance(c)).sum()
+    }
+}
+
+// Black is the transparent color of font bitmaps
+fn slice_cell(bitmap: &B2DO, glyph_size: (i32, i32), cell: (i32, i32)) -> Glyph {
+    let glyph_x_offset = cell.0 * glyph_size.0;
+    let glyph_y_offset = cell.1 * glyph_size.1;
 
-        Self { glyphs, glyph_size }
+    let mut glyph = Glyph::empty(glyph_size.0);
+
+    for glyph_x in 0..glyph_size.0 {
+        for glyph_y in 0..glyph_size.1 {
+            let color = bitmap.get_color(
+                glyph_x_offset as usize + glyph_x as usize,
+                glyph_y_offset as usize + glyph_y as usize,
+            );
+            if color == 0 {
+                continue;
+            }
+            glyph.pixels.push((glyph_x, glyph_y, color));
+        }
     }
+
+    glyph
 }
 
-pub fn blit_str_wrap<T: B2DT>(
+// Returns the top of the last line, relative to the offset and after any scrolling
+pub fn blit_str_wrap<T: B2DT<Pixel = u16>>(
     font: &Font,
     dest: &mut B2D<T>,
     string: &str,
     offset: (i32, i32),
     wrap_new_line_spaces: i32,
     scroll: bool,
+    style: TextStyle,
 ) -> i32 {
-    let mut col = 0;
+    let rules = LayoutRules {
+        indent: wrap_new_line_spaces * font.advance(' '),
+        ..Default::default()
+    };
+    let layout = TextLayout::new(font, string, dest.width - offset.0, rules, style);
+    let last_line = (layout.line_count as i32 - 1) * layout.line_height;
 
-    let mut dest_y = offset.1;
+    if !scroll {
+        let visible = (dest.height - offset.1) / layout.line_height.max(1);
+        for run in layout.runs.iter().filter(|run| (run.line as i32) < visible) {
+            layout.draw_run(font, dest, run, offset);
+        }
+        return last_line;
+    }
 
-    let max_width = dest.width - offset.0;
+    let overflow = (offset.1 + layout.size.1 - dest.height).max(0);
+    if overflow > 0 {
+        dest.scroll_up(overflow, 0);
+    }
+    layout.draw(font, dest, (offset.0, offset.1 - overflow));
+    last_line - overflow
+}
 
-    'words: for word in string.split(' ') {
-        let word_width = word.len() as i32 * font.glyph_size.0;
-        let mut char_wrap = false;
-        if word_width > max_width {
-            char_wrap = true;
-        } else if max_width - (col * font.glyph_size.0) - word_width <= 0 {
-            col = wrap_new_line_spaces;
-            dest_y += font.glyph_size.1;
+pub fn blit_str<T: B2DT<Pixel = u16>>(
+    font: &Font,
+    dest: &mut B2D<T>,
+    s: &str,
+    offset: (i32, i32),
+    style: TextStyle,
+) {
+    let mut dest_x = offset.0;
+    for (c, color) in style.apply(s) {
+        let advance = font.advance(c);
+        if dest_x > dest.width - advance {
+            return;
         }
-        for c in word.chars() {
-            if c as usize == CHARS_LINE_BREAK {
-                col = wrap_new_line_spaces;
-                dest_y += font.glyph_size.1;
-                continue;
-            }
-            let mut dest_x = offset.0 + (col * font.glyph_size.0);
-            if char_wrap && dest_x > dest.width - font.glyph_size.0 {
-                col = wrap_new_line_spaces;
-                dest_y += font.glyph_size.1;
-                if c as usize == CHARS_SPACE {
-                    continue;
-                }
-                dest_x = offset.0 + (col * font.glyph_size.0);
-            }
+        blit_char(font, dest, c, (dest_x, offset.1), style.with_color(color));
+        dest_x += advance;
+    }
+}
+
+// Effects go underneath, so the glyph itself is never covered by its own outline
+pub fn blit_char<T: B2DT<Pixel = u16>>(
+    font: &Font,
+    dest: &mut B2D<T>,
+    c: char,
+    offset: (i32, i32),
+    style: TextStyle,
+) {
+    let Some(glyph) = font.glyph(c) else {
+        return;
+    };
 
-            if dest_y + font.glyph_size.1 > dest.height {
-                if scroll {
-                    let scroll_amount = dest_y + font.glyph_size.1 - dest.height;
-                    dest_y = dest_y - scroll_amount;
-
-                    let scroll_length = (dest.width * scroll_amount) as usize;
-                    let dest_length = (dest.width * dest.height) as usize;
-                    dest.bitmap.copy_within(scroll_length..dest_length, 0);
-                    dest.bitmap[(dest_length - scroll_length)..].fill(0);
-                } else {
-                    break 'words;
-                }
+    match style.effect {
+        TextEffect::None => {}
+        TextEffect::Shadow(color) => {
+            let shadow = (offset.0 + SHADOW_OFFSET.0, offset.1 + SHADOW_OFFSET.1);
+            blit_glyph(dest, glyph, shadow, |_| color);
+        }
+        TextEffect::Outline(color) => {
+            for (x, y) in OUTLINE_OFFSETS {
+                blit_glyph(dest, glyph, (offset.0 + x, offset.1 + y), |_| color);
             }
-            blit_char(font, dest, c, (dest_x, dest_y));
-            col += 1;
         }
-        col += 1;
     }
 
-    return dest_y - offset.1;
+    match style.color {
+        Some(tint) => blit_glyph(dest, glyph, offset, |color| {
+            blend(color, color, BlendMode::Tint(tint))
+        }),
+        None => blit_glyph(dest, glyph, offset, |color| color),
+    }
 }
 
-pub fn blit_str<T: B2DT>(font: &Font, dest: &mut B2D<T>, s: &str, offset: (i32, i32)) {
-    let mut col = 0;
-    for c in s.chars() {
-        let dest_x = offset.0 + (col * font.glyph_size.0);
-        if dest_x > dest.width - font.glyph_size.0 {
-            return;
-        }
-        blit_char(font, dest, c, (dest_x, offset.1));
-        col += 1;
+// Bearings can reach outside the line, so glyphs are clipped
+fn blit_glyph<T: B2DT<Pixel = u16>>(
+    dest: &mut B2D<T>,
+    glyph: &Glyph,
+    offset: (i32, i32),
+    color: impl Fn(u16) -> u16,
+) {
+    for glyph_pixel in glyph.pixels.iter() {
+        dest.set_color_clipped(
+            glyph_pixel.0 + glyph.bearing.0 + offset.0,
+            glyph_pixel.1 + glyph.bearing.1 + offset.1,
+            color(glyph_pixel.2),
+        );
     }
 }
 
-pub fn blit_char<T: B2DT>(font: &Font, dest: &mut B2D<T>, c: char, offset: (i32, i32)) {
-    let u = c as usize;
-    if u <= CHARS_FIRST || u > CHARS_LAST {
-        return;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font_with(chars: &str) -> Font {
+        let glyphs = Vec::from_iter(chars.chars().map(|_| Glyph::empty(4)));
+        Font::from_glyphs(glyphs, chars.chars().zip(0..), 8)
     }
 
-    let u = u - CHARS_FIRST;
-    let glyph = &font.glyphs[u];
+    #[test]
+    fn missing_glyphs_use_the_fallback() {
+        let font = font_with("ab?");
+        assert_eq!(font.fallback, Some(2));
+        assert_eq!(font.glyph_map.get(&' '), Some(&3));
+        assert_eq!(font.advance(' '), 4);
+        assert_eq!(font.text_width("aé\tb"), 12);
 
-    for glyph_pixel in glyph.0.iter() {
-        dest.set_color(
-            glyph_pixel.0 + offset.0,
-            glyph_pixel.1 + offset.1,
-            glyph_pixel.2,
-        );
+        let font = font_with("ab\u{fffd}?");
+        assert_eq!(font.fallback, Some(2));
+
+        let font = font_with("ab");
+        assert_eq!(font.fallback, None);
+        assert_eq!(font.text_width("aéb"), 8);
     }
 }
@@ -6,7 +6,7 @@ use crate::{
     utils::is_inside,
 };
 
-use super::{B2D, B2DO, B2DS, B2DT};
+use super::{layout::TextLayout, text::Font, B2D, B2DO, B2DS, B2DT};
 
 pub struct WindowBorder {
     pub padding: i32,
@@ -49,6 +49,21 @@ impl VirtualWindow {
         }
     }
 
+    // Sized to the layout's bounding box with padding on every side
+    pub fn from_text(name: String, font: &Font, layout: &TextLayout, padding: i32) -> Self {
+        let window = Self::new(
+            name,
+            layout.size.0 + padding * 2,
+            layout.size.1 + padding * 2,
+        );
+        layout.draw(
+            font,
+            &mut window.buffer.borrow_mut(),
+            (padding - layout.offset.0, padding - layout.offset.1),
+        );
+        window
+    }
+
     pub fn with_xyz(mut self, x: i32, y: i32, z: i32) -> Self {
         self.x = x;
         self.y = y;
@@ -61,13 +76,13 @@ impl VirtualWindow {
         self
     }
 
-    pub fn blit_with_border<T: B2DT>(&mut self, dest: &mut B2D<T>, border: &WindowBorder) {
+    pub fn blit_with_border<T: B2DT<Pixel = u16>>(
+        &mut self,
+        dest: &mut B2D<T>,
+        border: &WindowBorder,
+    ) {
         let buffer = self.buffer.borrow();
-        dest.blit_full(
-            &buffer.bitmap,
-            (buffer.width, buffer.height),
-            (self.x, self.y),
-        );
+        dest.blit_buffer_full(&buffer, (self.x, self.y));
 
         // Top left
         dest.blit_region_masked(
@@ -1,6 +1,9 @@
+use std::rc::Rc;
+
 use crate::{
     buffer2d::{
-        text::{blit_char, blit_str_wrap, Font},
+        markup::COLOR_CODES,
+        text::{blit_char, blit_str_wrap, Font, TextStyle},
         B2DO, B2DS,
     },
     platform::input::{Input, InputCode},
@@ -10,10 +13,28 @@ use crate::{
 const CONSOLE_COLOR: u16 = color_from_tuple((0, 0, 0));
 const CONSOLE_LINE_SPACING: i32 = 4;
 const CONSOLE_INPUT_CAPACITY: usize = 64;
+const CONSOLE_PROMPT: char = ']';
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn color(self) -> Option<u16> {
+        match self {
+            Severity::Info => None,
+            Severity::Warning => Some(COLOR_CODES[3]),
+            Severity::Error => Some(COLOR_CODES[1]),
+        }
+    }
+}
 
 pub struct Console {
     width: i32,
-    font: Font,
+    font: Rc<Font>,
 
     is_open: bool,
     current_width: f32,
@@ -28,7 +49,7 @@ pub struct Console {
 }
 
 impl Console {
-    pub fn new(width: i32, height: i32, font: Font) -> Self {
+    pub fn new(width: i32, height: i32, font: Rc<Font>) -> Self {
         let input_height = font.glyph_size.0 + font.glyph_size.1;
 
         let output_next_y = font.glyph_size.0;
@@ -36,11 +57,17 @@ impl Console {
         let mut output_buffer = B2DO::new(width, output_height);
         output_buffer.bitmap.fill(CONSOLE_COLOR);
 
-        let input_char_x = 0;
+        let input_char_x = font.advance(CONSOLE_PROMPT);
         let input_y = height - font.glyph_size.0 - font.glyph_size.1;
         let mut input_buffer = B2DO::new(width, input_height);
         input_buffer.bitmap.fill(CONSOLE_COLOR);
-        blit_char(&font, &mut input_buffer, ']', (0, 0));
+        blit_char(
+            &font,
+            &mut input_buffer,
+            CONSOLE_PROMPT,
+            (0, 0),
+            TextStyle::default(),
+        );
 
         Self {
             width,
@@ -64,6 +91,22 @@ impl Console {
     }
 
     pub fn put_line(&mut self, text: &str) {
+        self.log(Severity::Info, text);
+    }
+
+    pub fn put_warning(&mut self, text: &str) {
+        self.log(Severity::Warning, text);
+    }
+
+    pub fn put_error(&mut self, text: &str) {
+        self.log(Severity::Error, text);
+    }
+
+    // Lines are drawn with markup, color codes inside them override the severity's color
+    pub fn log(&mut self, severity: Severity, text: &str) {
+        let style = TextStyle::default()
+            .with_color(severity.color())
+            .with_markup();
         let offset_y = blit_str_wrap(
             &self.font,
             &mut self.output_buffer,
@@ -71,6 +114,7 @@ impl Console {
             (self.font.glyph_size.0, self.output_next_y),
             2,
             true,
+            style,
         );
         self.output_next_y += offset_y + self.font.glyph_size.1 + CONSOLE_LINE_SPACING;
     }
@@ -93,17 +137,28 @@ impl Console {
             self.toggle();
         } else if self.is_open {
             if input.is_pressed(InputCode::Back) {
-                self.input_string.pop();
-                self.input_buffer
-                    .blit_fill((self.input_char_x, 0), self.font.glyph_size, 0);
-                self.input_char_x -= self.font.glyph_size.0;
+                // Overhanging glyphs may reach past their advance, so the rest of the line goes
+                if let Some(last_char) = self.input_string.pop() {
+                    self.input_char_x -= self.font.advance(last_char);
+                    self.input_buffer.blit_fill(
+                        (self.input_char_x, 0),
+                        (self.width, self.input_buffer.height),
+                        0,
+                    );
+                }
             } else if input.is_pressed(InputCode::Return) {
                 if self.input_string.len() != 0 {
                     let command_string = std::mem::take(&mut self.input_string);
                     self.put_line(command_string.as_str());
                     self.input_buffer.bitmap.fill(0);
-                    blit_char(&self.font, &mut self.input_buffer, ']', (0, 0));
-                    self.input_char_x = 0;
+                    blit_char(
+                        &self.font,
+                        &mut self.input_buffer,
+                        CONSOLE_PROMPT,
+                        (0, 0),
+                        TextStyle::default(),
+                    );
+                    self.input_char_x = self.font.advance(CONSOLE_PROMPT);
                 }
             } else if let Some(last_char) = input.last_char {
                 self.input_string.push(last_char);
@@ -111,9 +166,10 @@ impl Console {
                     &self.font,
                     &mut self.input_buffer,
                     last_char,
-                    (self.input_char_x + self.font.glyph_size.0, 0),
+                    (self.input_char_x, 0),
+                    TextStyle::default(),
                 );
-                self.input_char_x += self.font.glyph_size.0;
+                self.input_char_x += self.font.advance(last_char);
             }
         }
 
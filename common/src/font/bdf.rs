@@ -0,0 +1,252 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+    str::SplitWhitespace,
+};
+
+use crate::buffer2d::text::{Font, Glyph};
+
+use super::unpack_rows;
+
+#[derive(Debug)]
+pub enum BdfError {
+    Io(io::Error),
+    MissingBoundingBox,
+    InvalidLine(usize),
+    UnterminatedGlyph,
+}
+
+impl Display for BdfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BdfError::Io(error) => write!(f, "[BDF] IO error: {}", error),
+            BdfError::MissingBoundingBox => write!(f, "[BDF] FONTBOUNDINGBOX is missing!"),
+            BdfError::InvalidLine(line) => write!(f, "[BDF] Line {} is invalid!", line),
+            BdfError::UnterminatedGlyph => write!(f, "[BDF] Last glyph is missing ENDCHAR!"),
+        }
+    }
+}
+
+impl Error for BdfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BdfError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BdfError {
+    fn from(error: io::Error) -> Self {
+        BdfError::Io(error)
+    }
+}
+
+// Boxes are (width, height, x offset, y offset) with y pointing up from the baseline
+#[derive(Default)]
+struct BdfGlyph {
+    encoding: i32,
+    advance: Option<i32>,
+    bounding_box: Option<[i32; 4]>,
+    rows: Vec<u8>,
+}
+
+pub fn load_bdf(path: &str) -> Result<Font, BdfError> {
+    let mut f = File::open(path)?;
+    load_bdf_from_reader(&mut f)
+}
+
+pub fn load_bdf_from_reader<R: Read>(reader: &mut R) -> Result<Font, BdfError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    load_bdf_from_bytes(&bytes)
+}
+
+pub fn load_bdf_from_bytes(bytes: &[u8]) -> Result<Font, BdfError> {
+    let text = String::from_utf8_lossy(bytes);
+
+    let mut font_box = None;
+    let mut ascent = None;
+    let mut descent = None;
+    let mut glyphs = vec![];
+    let mut current: Option<BdfGlyph> = None;
+    let mut in_bitmap = false;
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+
+        if in_bitmap && keyword != "ENDCHAR" {
+            let glyph = open_glyph(&mut current, number)?;
+            let row_size = glyph
+                .bounding_box
+                .map_or(0, |[width, ..]| width.max(0) as usize);
+            let row = decode_hex(keyword, row_size.div_ceil(8), number)?;
+            glyph.rows.extend(row);
+            continue;
+        }
+
+        match keyword {
+            "FONTBOUNDINGBOX" => font_box = Some(parse_numbers::<4>(words, number)?),
+            "FONT_ASCENT" => ascent = Some(parse_numbers::<1>(words, number)?[0]),
+            "FONT_DESCENT" => descent = Some(parse_numbers::<1>(words, number)?[0]),
+            "STARTCHAR" => {
+                current = Some(BdfGlyph {
+                    encoding: -1,
+                    ..Default::default()
+                })
+            }
+            "ENCODING" => {
+                open_glyph(&mut current, number)?.encoding = parse_numbers::<1>(words, number)?[0]
+            }
+            "DWIDTH" => {
+                open_glyph(&mut current, number)?.advance =
+                    Some(parse_numbers::<1>(words, number)?[0])
+            }
+            "BBX" => {
+                open_glyph(&mut current, number)?.bounding_box =
+                    Some(parse_numbers::<4>(words, number)?)
+            }
+            "BITMAP" => {
+                // Glyphs without their own box use the font's
+                let glyph = open_glyph(&mut current, number)?;
+                glyph.bounding_box = glyph.bounding_box.or(font_box);
+                in_bitmap = true;
+            }
+            "ENDCHAR" => {
+                in_bitmap = false;
+                glyphs.push(current.take().ok_or(BdfError::InvalidLine(number))?);
+            }
+            _ => {}
+        }
+    }
+
+    if current.is_some() {
+        return Err(BdfError::UnterminatedGlyph);
+    }
+
+    let [_, font_height, _, font_y] = font_box.ok_or(BdfError::MissingBoundingBox)?;
+    let ascent = ascent.unwrap_or(font_height + font_y);
+    let descent = descent.unwrap_or(-font_y);
+
+    // Encodings are Unicode code points, which covers Latin-1 fonts as well
+    let (glyph_map, glyphs): (Vec<_>, Vec<_>) = glyphs
+        .into_iter()
+        .filter_map(|glyph| {
+            let c = char::from_u32(u32::try_from(glyph.encoding).ok()?)?;
+            let [width, height, x, y] = glyph.bounding_box.or(font_box)?;
+            let glyph = Glyph {
+                pixels: unpack_rows(&glyph.rows, width.max(0) as usize, height.max(0) as usize),
+                bearing: (x, ascent - height - y),
+                advance: glyph.advance.unwrap_or(width),
+            };
+            Some((c, glyph))
+        })
+        .enumerate()
+        .map(|(index, (c, glyph))| ((c, index), glyph))
+        .unzip();
+
+    Ok(Font::from_glyphs(glyphs, glyph_map, ascent + descent))
+}
+
+fn open_glyph(current: &mut Option<BdfGlyph>, line: usize) -> Result<&mut BdfGlyph, BdfError> {
+    current.as_mut().ok_or(BdfError::InvalidLine(line))
+}
+
+fn parse_numbers<const N: usize>(
+    mut words: SplitWhitespace,
+    line: usize,
+) -> Result<[i32; N], BdfError> {
+    let mut numbers = [0; N];
+    for number in numbers.iter_mut() {
+        *number = words
+            .next()
+            .and_then(|word| word.parse().ok())
+            .ok_or(BdfError::InvalidLine(line))?;
+    }
+    Ok(numbers)
+}
+
+// Rows may carry more hex digits than the box needs, the extra ones are padding
+fn decode_hex(row: &str, size: usize, line: usize) -> Result<Vec<u8>, BdfError> {
+    let mut bytes = vec![0; size];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let Some(digits) = row.get(i * 2..i * 2 + 2) else {
+            break;
+        };
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| BdfError::InvalidLine(line))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &str = "STARTFONT 2.1
+FONTBOUNDINGBOX 3 4 0 -1
+FONT_ASCENT 3
+FONT_DESCENT 1
+STARTCHAR space
+ENCODING 32
+DWIDTH 2 0
+BBX 0 0 0 0
+BITMAP
+ENDCHAR
+STARTCHAR a
+ENCODING 97
+DWIDTH 3 0
+BBX 2 2 0 0
+BITMAP
+C0
+40
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn loads_glyphs_with_bearing_and_advance() {
+        let font = load_bdf_from_bytes(FONT.as_bytes()).unwrap();
+        assert_eq!(font.glyph_size, (3, 4));
+
+        let glyph = font.glyph('a').unwrap();
+        assert_eq!(glyph.advance, 3);
+        assert_eq!(glyph.bearing, (0, 1));
+        let pixels = Vec::from_iter(glyph.pixels.iter().map(|(x, y, _)| (*x, *y)));
+        assert_eq!(pixels, [(0, 0), (1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn empty_bounding_box_is_a_blank_glyph() {
+        let font = load_bdf_from_bytes(FONT.as_bytes()).unwrap();
+        let space = font.glyph(' ').unwrap();
+        assert!(space.pixels.is_empty());
+        assert_eq!(space.advance, 2);
+    }
+
+    #[test]
+    fn rejects_broken_fonts() {
+        let unterminated = &FONT[..FONT.rfind("ENDCHAR").unwrap()];
+        assert!(matches!(
+            load_bdf_from_bytes(unterminated.as_bytes()),
+            Err(BdfError::UnterminatedGlyph)
+        ));
+
+        let no_box = FONT.replace("FONTBOUNDINGBOX 3 4 0 -1\n", "");
+        assert!(matches!(
+            load_bdf_from_bytes(no_box.as_bytes()),
+            Err(BdfError::MissingBoundingBox)
+        ));
+
+        let bad_hex = FONT.replace("C0", "ZZ");
+        assert!(matches!(
+            load_bdf_from_bytes(bad_hex.as_bytes()),
+            Err(BdfError::InvalidLine(16))
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+// Control codes in CP437 fonts are drawn as these symbols
+const CP437_LOW: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+const CP437_DELETE: char = '⌂';
+
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// How the 256 glyphs of a code page font map to characters
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Charset {
+    Ascii,
+    Cp437,
+    Latin1,
+}
+
+impl Charset {
+    pub fn decode(self, code: u8) -> Option<char> {
+        match self {
+            Charset::Ascii => code.is_ascii().then_some(code as char),
+            Charset::Cp437 => match code {
+                0 => None,
+                0x01..=0x1f => Some(CP437_LOW[code as usize]),
+                0x7f => Some(CP437_DELETE),
+                0x80.. => Some(CP437_HIGH[code as usize - 0x80]),
+                _ => Some(code as char),
+            },
+            Charset::Latin1 => Some(code as char),
+        }
+    }
+
+    // Glyph index and character for every code the charset defines
+    pub fn mapping(self) -> impl Iterator<Item = (char, usize)> {
+        (0..=u8::MAX).filter_map(move |code| Some((self.decode(code)?, code as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_code_pages() {
+        assert_eq!(Charset::Ascii.decode(b'A'), Some('A'));
+        assert_eq!(Charset::Ascii.decode(0x80), None);
+
+        assert_eq!(Charset::Cp437.decode(0), None);
+        assert_eq!(Charset::Cp437.decode(0x01), Some('☺'));
+        assert_eq!(Charset::Cp437.decode(b'~'), Some('~'));
+        assert_eq!(Charset::Cp437.decode(0x7f), Some('⌂'));
+        assert_eq!(Charset::Cp437.decode(0x82), Some('é'));
+        assert_eq!(Charset::Cp437.decode(0xdb), Some('█'));
+        assert_eq!(Charset::Cp437.decode(0xff), Some('\u{a0}'));
+
+        assert_eq!(Charset::Latin1.decode(0xe9), Some('é'));
+    }
+
+    #[test]
+    fn mapping_covers_every_defined_code() {
+        assert_eq!(Charset::Ascii.mapping().count(), 128);
+        assert_eq!(Charset::Cp437.mapping().count(), 255);
+        assert_eq!(Charset::Latin1.mapping().count(), 256);
+        assert!(Charset::Cp437.mapping().any(|entry| entry == ('╬', 0xce)));
+    }
+}
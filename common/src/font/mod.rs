@@ -0,0 +1,101 @@
+pub mod bdf;
+pub mod charset;
+pub mod psf;
+
+use std::{error::Error, fmt::Display, fs, io};
+
+use crate::{buffer2d::text::Font, utils::color_from_tuple};
+
+use self::{bdf::BdfError, psf::PsfError};
+
+// Bitmap fonts only store coverage, every set bit is drawn in this color
+pub const GLYPH_COLOR: u16 = color_from_tuple((31, 31, 31));
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FontFormat {
+    Bdf,
+    Psf1,
+    Psf2,
+}
+
+impl FontFormat {
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"STARTFONT") {
+            Some(FontFormat::Bdf)
+        } else if bytes.starts_with(&psf::PSF1_MAGIC) {
+            Some(FontFormat::Psf1)
+        } else if bytes.starts_with(&psf::PSF2_MAGIC) {
+            Some(FontFormat::Psf2)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(io::Error),
+    UnknownFormat,
+    Bdf(BdfError),
+    Psf(PsfError),
+}
+
+impl Display for FontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontError::Io(error) => write!(f, "[Font] IO error: {}", error),
+            FontError::UnknownFormat => write!(f, "[Font] Unknown font format!"),
+            FontError::Bdf(error) => error.fmt(f),
+            FontError::Psf(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for FontError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FontError::Io(error) => Some(error),
+            FontError::UnknownFormat => None,
+            FontError::Bdf(error) => Some(error),
+            FontError::Psf(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for FontError {
+    fn from(error: io::Error) -> Self {
+        FontError::Io(error)
+    }
+}
+
+pub fn load_font(path: &str) -> Result<Font, FontError> {
+    load_font_from_bytes(&fs::read(path)?)
+}
+
+pub fn load_font_from_bytes(bytes: &[u8]) -> Result<Font, FontError> {
+    match FontFormat::detect(bytes) {
+        Some(FontFormat::Bdf) => bdf::load_bdf_from_bytes(bytes).map_err(FontError::Bdf),
+        Some(FontFormat::Psf1 | FontFormat::Psf2) => {
+            psf::load_psf_from_bytes(bytes).map_err(FontError::Psf)
+        }
+        None => Err(FontError::UnknownFormat),
+    }
+}
+
+// Rows are packed MSB first and padded to whole bytes
+fn unpack_rows(data: &[u8], width: usize, height: usize) -> Vec<(i32, i32, u16)> {
+    let row_size = width.div_ceil(8);
+    let mut pixels = vec![];
+    // Blank glyphs like the space often have an empty box
+    if row_size == 0 {
+        return pixels;
+    }
+    for (y, row) in data.chunks(row_size).take(height).enumerate() {
+        for x in 0..width.min(row.len() * 8) {
+            if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                pixels.push((x as i32, y as i32, GLYPH_COLOR));
+            }
+        }
+    }
+    pixels
+}
@@ -0,0 +1,310 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::{
+    buffer2d::text::{Font, Glyph},
+    utils::{read_u16, read_u32},
+};
+
+use super::{charset::Charset, unpack_rows};
+
+pub(super) const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+pub(super) const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_UNICODE: u8 = 0x02 | 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQUENCE: u16 = 0xFFFE;
+const PSF1_WIDTH: usize = 8;
+
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_FLAG_UNICODE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+
+#[derive(Debug)]
+pub enum PsfError {
+    Io(io::Error),
+    InvalidMagic,
+    InvalidHeader,
+    TruncatedGlyphs { expected: usize, actual: usize },
+}
+
+impl Display for PsfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PsfError::Io(error) => write!(f, "[PSF] IO error: {}", error),
+            PsfError::InvalidMagic => write!(f, "[PSF] Magic didn't match!"),
+            PsfError::InvalidHeader => write!(f, "[PSF] Invalid header!"),
+            PsfError::TruncatedGlyphs { expected, actual } => write!(
+                f,
+                "[PSF] Glyph data is truncated ({} of {} bytes)!",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl Error for PsfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PsfError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PsfError {
+    fn from(error: io::Error) -> Self {
+        PsfError::Io(error)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PsfVersion {
+    Psf1,
+    Psf2,
+}
+
+struct PsfHeader {
+    version: PsfVersion,
+    has_unicode_table: bool,
+    glyph_count: usize,
+    glyph_bytes: usize,
+    width: usize,
+    height: usize,
+    data_offset: usize,
+}
+
+pub fn load_psf(path: &str) -> Result<Font, PsfError> {
+    let mut f = File::open(path)?;
+    load_psf_from_reader(&mut f)
+}
+
+pub fn load_psf_from_reader<R: Read>(reader: &mut R) -> Result<Font, PsfError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    load_psf_from_bytes(&bytes)
+}
+
+// PSF fonts are monospaced, without a Unicode table the glyphs are taken to be CP437
+pub fn load_psf_from_bytes(bytes: &[u8]) -> Result<Font, PsfError> {
+    let header = read_header(bytes)?;
+
+    let data_size = header.glyph_count * header.glyph_bytes;
+    let data = bytes
+        .get(header.data_offset..header.data_offset + data_size)
+        .ok_or(PsfError::TruncatedGlyphs {
+            expected: data_size,
+            actual: bytes.len().saturating_sub(header.data_offset),
+        })?;
+
+    let glyphs = Vec::from_iter(data.chunks_exact(header.glyph_bytes).map(|data| Glyph {
+        pixels: unpack_rows(data, header.width, header.height),
+        bearing: (0, 0),
+        advance: header.width as i32,
+    }));
+
+    let glyph_map = match header.has_unicode_table {
+        true => read_unicode_table(&bytes[header.data_offset + data_size..], header.version),
+        false => Vec::from_iter(Charset::Cp437.mapping()),
+    };
+
+    Ok(Font::from_glyphs(glyphs, glyph_map, header.height as i32))
+}
+
+// Every glyph lists the characters it stands for, sequences after the marker are skipped
+fn read_unicode_table(table: &[u8], version: PsfVersion) -> Vec<(char, usize)> {
+    let mut glyph_map = vec![];
+    match version {
+        PsfVersion::Psf1 => {
+            let mut index = 0;
+            let mut in_sequence = false;
+            for value in table.chunks_exact(2).map(|pair| read_u16(pair, 0)) {
+                match value {
+                    PSF1_SEPARATOR => {
+                        index += 1;
+                        in_sequence = false;
+                    }
+                    PSF1_START_SEQUENCE => in_sequence = true,
+                    value if !in_sequence => {
+                        if let Some(c) = char::from_u32(value as u32) {
+                            glyph_map.push((c, index));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        PsfVersion::Psf2 => {
+            for (index, entry) in table.split(|byte| *byte == PSF2_SEPARATOR).enumerate() {
+                let singles = entry
+                    .split(|byte| *byte == PSF2_START_SEQUENCE)
+                    .next()
+                    .unwrap_or_default();
+                if let Ok(singles) = std::str::from_utf8(singles) {
+                    glyph_map.extend(singles.chars().map(|c| (c, index)));
+                }
+            }
+        }
+    }
+    glyph_map
+}
+
+fn read_header(bytes: &[u8]) -> Result<PsfHeader, PsfError> {
+    if bytes.starts_with(&PSF1_MAGIC) && bytes.len() >= PSF1_HEADER_SIZE {
+        let mode = bytes[2];
+        let height = bytes[3] as usize;
+        if height == 0 {
+            return Err(PsfError::InvalidHeader);
+        }
+
+        return Ok(PsfHeader {
+            version: PsfVersion::Psf1,
+            has_unicode_table: mode & PSF1_MODE_UNICODE != 0,
+            glyph_count: match mode & PSF1_MODE_512 {
+                0 => 256,
+                _ => 512,
+            },
+            glyph_bytes: height,
+            width: PSF1_WIDTH,
+            height,
+            data_offset: PSF1_HEADER_SIZE,
+        });
+    }
+
+    if bytes.starts_with(&PSF2_MAGIC) && bytes.len() >= PSF2_HEADER_SIZE {
+        let header = PsfHeader {
+            version: PsfVersion::Psf2,
+            has_unicode_table: read_u32(bytes, 12) & PSF2_FLAG_UNICODE != 0,
+            data_offset: read_u32(bytes, 8) as usize,
+            glyph_count: read_u32(bytes, 16) as usize,
+            glyph_bytes: read_u32(bytes, 20) as usize,
+            height: read_u32(bytes, 24) as usize,
+            width: read_u32(bytes, 28) as usize,
+        };
+
+        // Rows are padded to whole bytes, anything else is not a font we can read
+        if header.width == 0
+            || header.height == 0
+            || header.glyph_bytes != header.height * header.width.div_ceil(8)
+        {
+            return Err(PsfError::InvalidHeader);
+        }
+        return Ok(header);
+    }
+
+    Err(PsfError::InvalidMagic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn psf2(width: u32, height: u32, glyph_count: u32, flags: u32) -> Vec<u8> {
+        let glyph_bytes = height * width.div_ceil(8);
+        let mut bytes = PSF2_MAGIC.to_vec();
+        for value in [
+            0,
+            PSF2_HEADER_SIZE as u32,
+            flags,
+            glyph_count,
+            glyph_bytes,
+            height,
+            width,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.resize(bytes.len() + (glyph_count * glyph_bytes) as usize, 0);
+        bytes
+    }
+
+    #[test]
+    fn loads_psf1() {
+        let mut bytes = vec![PSF1_MAGIC[0], PSF1_MAGIC[1], 0, 2];
+        bytes.resize(PSF1_HEADER_SIZE + 256 * 2, 0);
+        // 'A' gets its left column set
+        bytes[PSF1_HEADER_SIZE + 'A' as usize * 2..][..2].copy_from_slice(&[0x80, 0x80]);
+
+        let font = load_psf_from_bytes(&bytes).unwrap();
+        assert_eq!(font.glyph_size, (8, 2));
+        assert_eq!(font.glyph('A').unwrap().pixels.len(), 2);
+    }
+
+    #[test]
+    fn psf_without_unicode_table_is_cp437() {
+        let font = load_psf_from_bytes(&psf2(8, 1, 256, 0)).unwrap();
+        assert_eq!(font.glyph_map.get(&'é'), Some(&0x82));
+        assert_eq!(font.glyph_map.get(&'☺'), Some(&0x01));
+    }
+
+    #[test]
+    fn reads_unicode_tables() {
+        // Glyph 0 is 'a' and 'α', glyph 1 is 'b' with a skipped sequence
+        let mut bytes = psf2(8, 1, 2, PSF2_FLAG_UNICODE);
+        bytes.extend("aα".as_bytes());
+        bytes.push(PSF2_SEPARATOR);
+        bytes.push(b'b');
+        bytes.push(PSF2_START_SEQUENCE);
+        bytes.extend("c\u{301}".as_bytes());
+        bytes.push(PSF2_SEPARATOR);
+
+        let font = load_psf_from_bytes(&bytes).unwrap();
+        assert_eq!(font.glyph_map.get(&'a'), Some(&0));
+        assert_eq!(font.glyph_map.get(&'α'), Some(&0));
+        assert_eq!(font.glyph_map.get(&'b'), Some(&1));
+        assert_eq!(font.glyph_map.get(&'c'), None);
+
+        let mut bytes = vec![PSF1_MAGIC[0], PSF1_MAGIC[1], PSF1_MODE_UNICODE, 1];
+        bytes.resize(PSF1_HEADER_SIZE + 256, 0);
+        for value in [0x41, 0x391, PSF1_SEPARATOR, 0x42, PSF1_SEPARATOR] {
+            bytes.extend(value.to_le_bytes());
+        }
+
+        let font = load_psf_from_bytes(&bytes).unwrap();
+        assert_eq!(font.glyph_map.get(&'A'), Some(&0));
+        assert_eq!(font.glyph_map.get(&'Α'), Some(&0));
+        assert_eq!(font.glyph_map.get(&'B'), Some(&1));
+    }
+
+    #[test]
+    fn loads_psf2_with_wide_rows() {
+        let mut bytes = psf2(10, 1, 256, 0);
+        // Bits past the width are padding
+        bytes[PSF2_HEADER_SIZE + 'x' as usize * 2..][..2].copy_from_slice(&[0x80, 0xff]);
+
+        let font = load_psf_from_bytes(&bytes).unwrap();
+        assert_eq!(font.glyph_size, (10, 1));
+        let pixels = Vec::from_iter(font.glyph('x').unwrap().pixels.iter().map(|(x, ..)| *x));
+        assert_eq!(pixels, [0, 8, 9]);
+    }
+
+    #[test]
+    fn rejects_broken_fonts() {
+        let bytes = psf2(8, 8, 256, 0);
+        assert!(matches!(
+            load_psf_from_bytes(&bytes[..bytes.len() - 1]),
+            Err(PsfError::TruncatedGlyphs {
+                expected: 2048,
+                actual: 2047
+            })
+        ));
+
+        let mut bytes = psf2(8, 8, 1, 0);
+        bytes[20] = 7;
+        assert!(matches!(
+            load_psf_from_bytes(&bytes),
+            Err(PsfError::InvalidHeader)
+        ));
+
+        assert!(matches!(
+            load_psf_from_bytes(&[0; 32]),
+            Err(PsfError::InvalidMagic)
+        ));
+    }
+}
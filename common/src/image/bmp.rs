@@ -1,12 +1,27 @@
 use std::{
+    error::Error,
+    fmt::Display,
     fs::File,
-    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
     mem, slice,
 };
 
-use crate::{buffer2d::B2DO, utils::color_from_tuple};
+use crate::buffer2d::{dither::Dither, pixel::DirectPixel, B2D, B2DO, B2DT, MASK_COLOR};
+
+use super::{checked_pixel_count, color_from_rgb8, ALPHA_THRESHOLD};
 
 const SIGNATURE: u16 = 19778;
+const FILE_HEADER_SIZE: u64 = 14;
+const INFO_HEADER_END: usize = 54;
+
+const COMPRESSION_RGB: u32 = 0;
+const COMPRESSION_RLE8: u32 = 1;
+const COMPRESSION_RLE4: u32 = 2;
+const COMPRESSION_BITFIELDS: u32 = 3;
+
+const RLE_END_OF_LINE: u8 = 0;
+const RLE_END_OF_BITMAP: u8 = 1;
+const RLE_DELTA: u8 = 2;
 
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
@@ -34,113 +49,721 @@ struct BMPHeader {
 }
 const HEADER_SIZE: usize = mem::size_of::<BMPHeader>();
 
-pub fn load_bmp(path: &str) -> B2DO {
-    let mut f = File::open(path).expect("[BMP] Error reading file!");
+#[derive(Debug)]
+pub enum BmpError {
+    Io(io::Error),
+    InvalidSignature(u16),
+    UnsupportedBitDepth(u16),
+    UnsupportedCompression(u32),
+    UnsupportedFormat {
+        bits_per_pixel: u16,
+        compression: u32,
+    },
+    TruncatedPixelData {
+        expected: usize,
+        actual: usize,
+    },
+    InvalidDimensions {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Display for BmpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BmpError::Io(error) => write!(f, "[BMP] IO error: {}", error),
+            BmpError::InvalidSignature(signature) => {
+                write!(f, "[BMP] Signature {:#06x} didn't match!", signature)
+            }
+            BmpError::UnsupportedBitDepth(bits_per_pixel) => {
+                write!(f, "[BMP] Unsupported bit depth {}!", bits_per_pixel)
+            }
+            BmpError::UnsupportedCompression(compression) => {
+                write!(
+                    f,
+                    "[BMP] Compression mode {} is not supported!",
+                    compression
+                )
+            }
+            BmpError::UnsupportedFormat {
+                bits_per_pixel,
+                compression,
+            } => write!(
+                f,
+                "[BMP] Wrong combination of compression {} and bit depth {}!",
+                compression, bits_per_pixel
+            ),
+            BmpError::TruncatedPixelData { expected, actual } => write!(
+                f,
+                "[BMP] Pixel data is truncated ({} of {} bytes)!",
+                actual, expected
+            ),
+            BmpError::InvalidDimensions { width, height } => {
+                write!(f, "[BMP] Invalid dimensions {}x{}!", width, height)
+            }
+        }
+    }
+}
+
+impl Error for BmpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BmpError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BmpError {
+    fn from(error: io::Error) -> Self {
+        BmpError::Io(error)
+    }
+}
+
+pub fn load_bmp(path: &str, dither: Dither) -> Result<B2DO, BmpError> {
+    let mut f = File::open(path)?;
+    load_bmp_from_reader(&mut f, dither)
+}
+
+pub fn load_bmp_from_bytes(bytes: &[u8], dither: Dither) -> Result<B2DO, BmpError> {
+    load_bmp_from_reader(&mut Cursor::new(bytes), dither)
+}
+
+// Dithering only applies to true color images, palettes are converted once
+pub fn load_bmp_from_reader<R: Read + Seek>(
+    reader: &mut R,
+    dither: Dither,
+) -> Result<B2DO, BmpError> {
     let mut header: BMPHeader = unsafe { mem::zeroed() };
 
     unsafe {
         let header_slice = slice::from_raw_parts_mut(&mut header as *mut _ as *mut u8, HEADER_SIZE);
-        f.read_exact(header_slice).unwrap();
+        reader.read_exact(&mut header_slice[..INFO_HEADER_END])?;
+
+        if header.signature != SIGNATURE {
+            return Err(BmpError::InvalidSignature(header.signature));
+        }
+
+        // Masks follow a plain info header only when bitfields are used,
+        // newer headers always carry them and V3+ adds the alpha mask
+        let mask_count = match header.header_size {
+            40 if header.compression == COMPRESSION_BITFIELDS => 3,
+            40 => 0,
+            52 => 3,
+            _ => 4,
+        };
+        reader.read_exact(&mut header_slice[INFO_HEADER_END..INFO_HEADER_END + mask_count * 4])?;
+    }
+
+    if !matches!(header.bits_per_pixel, 1 | 4 | 8 | 16 | 24 | 32) {
+        return Err(BmpError::UnsupportedBitDepth(header.bits_per_pixel));
+    }
+
+    if !matches!(
+        header.compression,
+        COMPRESSION_RGB | COMPRESSION_RLE8 | COMPRESSION_RLE4 | COMPRESSION_BITFIELDS
+    ) {
+        return Err(BmpError::UnsupportedCompression(header.compression));
     }
 
-    if header.signature != SIGNATURE {
-        panic!("[BMP] Signature didn't match!");
+    if header.compression != COMPRESSION_BITFIELDS {
+        (header.red_mask, header.green_mask, header.blue_mask) = match header.bits_per_pixel {
+            16 => (0x7C00, 0x03E0, 0x001F),
+            _ => (0xFF0000, 0x00FF00, 0x0000FF),
+        };
+        header.alpha_mask = 0;
     }
 
-    if header.bits_per_pixel != 24 && header.bits_per_pixel != 32 {
-        panic!("[BMP] Unsupported bit depth!");
+    let dimensions = Dimensions {
+        width: header.width.unsigned_abs() as usize,
+        height: header.height.unsigned_abs() as usize,
+        top_down: header.height < 0,
+        stride: row_stride(header.width.unsigned_abs() as usize, header.bits_per_pixel),
+    };
+
+    let palette = if header.bits_per_pixel <= 8 {
+        read_palette(reader, &header)?
+    } else {
+        vec![]
+    };
+
+    reader.seek(SeekFrom::Start(header.offset as u64))?;
+
+    let pixels = match (header.compression, header.bits_per_pixel) {
+        (COMPRESSION_RGB, 1 | 4 | 8) => {
+            let color_buf = read_pixel_data(reader, &dimensions)?;
+            decode_indexed(&color_buf, &header, &dimensions, &palette)
+        }
+        (COMPRESSION_RLE8, 8) | (COMPRESSION_RLE4, 4) => {
+            let color_buf = read_rle_data(reader, &header)?;
+            decode_rle(&color_buf, &header, &dimensions, &palette)?
+        }
+        (COMPRESSION_RGB, 16 | 24 | 32) | (COMPRESSION_BITFIELDS, 16 | 32) => {
+            let color_buf = read_pixel_data(reader, &dimensions)?;
+            decode_rgb(&color_buf, &header, &dimensions, dither)
+        }
+        _ => {
+            return Err(BmpError::UnsupportedFormat {
+                bits_per_pixel: header.bits_per_pixel,
+                compression: header.compression,
+            })
+        }
+    };
+
+    Ok(B2DO {
+        width: dimensions.width as i32,
+        height: dimensions.height as i32,
+        stride: dimensions.width as i32,
+        bitmap: pixels,
+    })
+}
+
+// Any direct color format can be saved, channels are written at 8 bits
+pub fn save_bmp<T: B2DT>(buffer: &B2D<T>, path: &str) -> Result<(), BmpError>
+where
+    T::Pixel: DirectPixel,
+{
+    let mut f = BufWriter::new(File::create(path)?);
+    save_bmp_to_writer(buffer, &mut f)?;
+    f.flush()?;
+    Ok(())
+}
+
+pub fn save_bmp_to_writer<T: B2DT, W: Write>(
+    buffer: &B2D<T>,
+    writer: &mut W,
+) -> Result<(), BmpError>
+where
+    T::Pixel: DirectPixel,
+{
+    let width = buffer.width as usize;
+    let height = buffer.height as usize;
+    let stride = row_stride(width, 24);
+    let size_of_bitmap = stride * height;
+    let offset = INFO_HEADER_END;
+
+    writer.write_all(&SIGNATURE.to_le_bytes())?;
+    writer.write_all(&((offset + size_of_bitmap) as u32).to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&(offset as u32).to_le_bytes())?;
+    writer.write_all(&40u32.to_le_bytes())?;
+    writer.write_all(&buffer.width.to_le_bytes())?;
+    writer.write_all(&buffer.height.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&24u16.to_le_bytes())?;
+    writer.write_all(&COMPRESSION_RGB.to_le_bytes())?;
+    writer.write_all(&(size_of_bitmap as u32).to_le_bytes())?;
+    writer.write_all(&[0; 16])?;
+
+    let mut row_buf = vec![0; stride];
+    for y in (0..height).rev() {
+        for (x, c) in row_buf.chunks_exact_mut(3).take(width).enumerate() {
+            let [r, g, b, _] = buffer.get_color(x, y).to_rgba8();
+            c.copy_from_slice(&[b, g, r]);
+        }
+        writer.write_all(&row_buf)?;
     }
 
-    if header.compression != 0 && header.compression != 3 {
-        panic!("[BMP] Compression mode {:?} is not supported!", {
-            header.compression
+    Ok(())
+}
+
+// The header sizes are checked against what is left of the file before anything is allocated
+fn read_pixel_data<R: Read + Seek>(
+    reader: &mut R,
+    dimensions: &Dimensions,
+) -> Result<Vec<u8>, BmpError> {
+    let expected =
+        dimensions
+            .stride
+            .checked_mul(dimensions.height)
+            .ok_or(BmpError::InvalidDimensions {
+                width: dimensions.width,
+                height: dimensions.height,
+            })?;
+
+    let position = reader.stream_position()?;
+    let remaining = reader.seek(SeekFrom::End(0))?.saturating_sub(position);
+    reader.seek(SeekFrom::Start(position))?;
+    let remaining = usize::try_from(remaining).unwrap_or(usize::MAX);
+    if remaining < expected {
+        return Err(BmpError::TruncatedPixelData {
+            expected,
+            actual: remaining,
         });
     }
 
-    let bytes_per_pixel = (header.bits_per_pixel / u8::BITS as u16) as usize;
+    let mut color_buf = vec![0; expected];
+    reader.read_exact(&mut color_buf)?;
+    Ok(color_buf)
+}
 
-    let mut color_buf = vec![0; (header.width * header.height) as usize * bytes_per_pixel];
-    f.seek(SeekFrom::Start(header.offset as u64))
-        .expect("[BMP] Error seeking!");
-    f.read_exact(color_buf.as_mut_slice())
-        .expect("[BMP] Error reading!");
+fn read_rle_data<R: Read>(reader: &mut R, header: &BMPHeader) -> Result<Vec<u8>, BmpError> {
+    let mut color_buf = vec![];
+    match header.size_of_bitmap {
+        0 => reader.read_to_end(&mut color_buf)?,
+        size => reader.take(size as u64).read_to_end(&mut color_buf)?,
+    };
+    Ok(color_buf)
+}
 
-    let pixels = if header.compression == 0 && header.bits_per_pixel == 24 {
-        if header.width != header.height
-            || !((header.width & (header.width - 1)) == 0)
-            || !((header.height & (header.height - 1)) == 0)
-        {
-            panic!("[BMP] Width and/or height are not power of two!");
+fn read_palette<R: Read + Seek>(reader: &mut R, header: &BMPHeader) -> Result<Vec<u16>, BmpError> {
+    let count = match header.colors_used {
+        0 => 1 << header.bits_per_pixel,
+        colors_used => (colors_used as usize).min(1 << header.bits_per_pixel),
+    };
+
+    reader.seek(SeekFrom::Start(
+        FILE_HEADER_SIZE + header.header_size as u64,
+    ))?;
+    let mut palette_buf = vec![0; count * 4];
+    reader.read_exact(&mut palette_buf)?;
+
+    // Entries are stored as BGRX, an entry that converts to MASK_COLOR stays transparent
+    Ok(Vec::from_iter(
+        palette_buf
+            .chunks_exact(4)
+            .map(|c| color_from_rgb8(c[2], c[1], c[0])),
+    ))
+}
+
+fn decode_indexed(
+    color_buf: &[u8],
+    header: &BMPHeader,
+    dimensions: &Dimensions,
+    palette: &[u16],
+) -> Vec<u16> {
+    let bits_per_pixel = header.bits_per_pixel as usize;
+    let pixels_per_byte = 8 / bits_per_pixel;
+    let index_mask = (1 << bits_per_pixel) - 1;
+
+    let mut pixels = Vec::with_capacity(dimensions.width * dimensions.height);
+    for y in 0..dimensions.height {
+        let row = dimensions.row(color_buf, y);
+        for x in 0..dimensions.width {
+            let byte = row[x / pixels_per_byte];
+            let shift = 8 - bits_per_pixel * (x % pixels_per_byte + 1);
+            let index = (byte as usize >> shift) & index_mask;
+            pixels.push(palette_color(palette, index));
         }
+    }
+    pixels
+}
 
-        Vec::from_iter(color_buf.rchunks_exact_mut(bytes_per_pixel).map(|c| {
-            color_from_tuple((
-                (c[2] as f32 / 256.0 * 32.0) as u16,
-                (c[1] as f32 / 256.0 * 32.0) as u16,
-                (c[0] as f32 / 256.0 * 32.0) as u16,
-            ))
-        }))
-    } else if header.compression == 3 && header.bits_per_pixel == 32 {
-        // let red_mask = header.red_mask;
-        // let green_mask = header.green_mask;
-        // let blue_mask = header.blue_mask;
-        // let alpha_mask = header.alpha_mask;
-
-        // let red_shift = red_mask.trailing_zeros();
-        // let green_shift = green_mask.trailing_zeros();
-        // let blue_shift = blue_mask.trailing_zeros();
-        // let alpha_shift = alpha_mask.trailing_zeros();
-
-        // color_buf
-        //     .chunks_exact_mut(bytes_per_pixel)
-        //     .for_each(|color| {
-        //         shift_channels(color, red_shift, green_shift, blue_shift, alpha_shift)
-        //     });
-
-        // un-mirror Y
-        color_buf = Vec::from_iter(
-            color_buf
-                .rchunks(4 * header.width as usize)
-                .flatten()
-                .cloned(),
-        );
+fn decode_rle(
+    color_buf: &[u8],
+    header: &BMPHeader,
+    dimensions: &Dimensions,
+    palette: &[u16],
+) -> Result<Vec<u16>, BmpError> {
+    let width = dimensions.width;
+    let height = dimensions.height;
+    let top_down = dimensions.top_down;
+    let nibbles = header.compression == COMPRESSION_RLE4;
 
-        Vec::from_iter(color_buf.chunks_exact_mut(4).map(|c| {
-            color_from_tuple((
-                (c[2] as f32 / 256.0 * 32.0) as u16,
-                (c[1] as f32 / 256.0 * 32.0) as u16,
-                (c[0] as f32 / 256.0 * 32.0) as u16,
-            ))
-        }))
-    } else {
-        panic!("[BMP] Wrong combination of compression and bit depth!");
+    let pixel_count =
+        checked_pixel_count(width, height).ok_or(BmpError::InvalidDimensions { width, height })?;
+
+    // Pixels skipped with delta or end of line escapes stay transparent
+    let mut pixels = vec![MASK_COLOR; pixel_count];
+    let mut put = |x: usize, y: usize, index: u8| {
+        if x < width && y < height {
+            let y = if top_down { y } else { height - 1 - y };
+            pixels[y * width + x] = palette_color(palette, index as usize);
+        }
     };
 
-    B2DO {
-        width: header.width,
-        height: header.height,
-        bitmap: pixels,
+    let mut rle = RleReader {
+        data: color_buf,
+        position: 0,
+    };
+    let mut x = 0;
+    let mut y = 0;
+
+    while rle.position < color_buf.len() {
+        let count = rle.next()?;
+        let value = rle.next()?;
+
+        if count > 0 {
+            for i in 0..count {
+                let index = match (nibbles, i % 2) {
+                    (true, 0) => value >> 4,
+                    (true, _) => value & 0x0F,
+                    (false, _) => value,
+                };
+                put(x, y, index);
+                x += 1;
+            }
+            continue;
+        }
+
+        match value {
+            RLE_END_OF_LINE => {
+                x = 0;
+                y += 1;
+            }
+            RLE_END_OF_BITMAP => break,
+            RLE_DELTA => {
+                x += rle.next()? as usize;
+                y += rle.next()? as usize;
+            }
+            count => {
+                let mut byte = 0;
+                for i in 0..count {
+                    let index = if nibbles {
+                        if i % 2 == 0 {
+                            byte = rle.next()?;
+                            byte >> 4
+                        } else {
+                            byte & 0x0F
+                        }
+                    } else {
+                        rle.next()?
+                    };
+                    put(x, y, index);
+                    x += 1;
+                }
+
+                // Absolute runs are padded to a 16-bit boundary
+                let run_bytes = match nibbles {
+                    true => (count as usize).div_ceil(2),
+                    false => count as usize,
+                };
+                if run_bytes % 2 == 1 {
+                    rle.next()?;
+                }
+            }
+        }
     }
+
+    Ok(pixels)
 }
 
-fn shift32(value: u32, shift: u32) -> u32 {
-    value << shift
+fn decode_rgb(
+    color_buf: &[u8],
+    header: &BMPHeader,
+    dimensions: &Dimensions,
+    dither: Dither,
+) -> Vec<u16> {
+    let bytes_per_pixel = (header.bits_per_pixel / u8::BITS as u16) as usize;
+    let red = ChannelMask::new(header.red_mask);
+    let green = ChannelMask::new(header.green_mask);
+    let blue = ChannelMask::new(header.blue_mask);
+    let alpha = ChannelMask::new(header.alpha_mask);
+
+    let mut pixels = Vec::with_capacity(dimensions.width * dimensions.height);
+    for y in 0..dimensions.height {
+        let row = dimensions.row(color_buf, y);
+        for (x, c) in row
+            .chunks_exact(bytes_per_pixel)
+            .take(dimensions.width)
+            .enumerate()
+        {
+            let value = c
+                .iter()
+                .rev()
+                .fold(0u32, |value, byte| (value << 8) | *byte as u32);
+
+            if alpha.mask != 0 && alpha.extract(value) < ALPHA_THRESHOLD {
+                pixels.push(MASK_COLOR);
+            } else {
+                pixels.push(dither.color_from_rgb8(
+                    red.extract(value),
+                    green.extract(value),
+                    blue.extract(value),
+                    x as i32,
+                    y as i32,
+                ));
+            }
+        }
+    }
+    pixels
+}
+
+struct Dimensions {
+    width: usize,
+    height: usize,
+    top_down: bool,
+    stride: usize,
+}
+
+impl Dimensions {
+    fn row<'a>(&self, color_buf: &'a [u8], y: usize) -> &'a [u8] {
+        let row = if self.top_down {
+            y
+        } else {
+            self.height - 1 - y
+        };
+        &color_buf[row * self.stride..(row + 1) * self.stride]
+    }
+}
+
+struct ChannelMask {
+    mask: u32,
+    shift: u32,
+    max: u32,
+}
+
+impl ChannelMask {
+    fn new(mask: u32) -> Self {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+        Self {
+            mask,
+            shift,
+            max: mask >> shift,
+        }
+    }
+
+    // Scales the masked channel to 8 bits regardless of its width
+    fn extract(&self, value: u32) -> u8 {
+        if self.max == 0 {
+            return 0;
+        }
+        (((value & self.mask) >> self.shift) as u64 * 255 / self.max as u64) as u8
+    }
+}
+
+struct RleReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl RleReader<'_> {
+    fn next(&mut self) -> Result<u8, BmpError> {
+        let byte = self
+            .data
+            .get(self.position)
+            .copied()
+            .ok_or(BmpError::TruncatedPixelData {
+                expected: self.position + 1,
+                actual: self.data.len(),
+            })?;
+        self.position += 1;
+        Ok(byte)
+    }
+}
+
+fn row_stride(width: usize, bits_per_pixel: u16) -> usize {
+    (width * bits_per_pixel as usize).div_ceil(32) * 4
+}
+
+fn palette_color(palette: &[u16], index: usize) -> u16 {
+    palette.get(index).copied().unwrap_or(0)
 }
 
-fn shift_channels(
-    array: &mut [u8],
-    red_shift: u32,
-    green_shift: u32,
-    blue_shift: u32,
-    alpha_shift: u32,
-) {
-    let shifted = shift32(array[0] as u32, red_shift)
-        + shift32(array[1] as u32, green_shift)
-        + shift32(array[2] as u32, blue_shift)
-        + shift32(array[3] as u32, alpha_shift);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 31 << 10;
+    const GREEN: u16 = 31 << 5;
+    const BLUE: u16 = 31;
+    const WHITE: u16 = 0x7FFF;
+
+    // Palettes and masks go right after the info header, the pixel data after the whole header
+    fn bmp(
+        (width, height): (i32, i32),
+        bits_per_pixel: u16,
+        compression: u32,
+        header_size: u32,
+        tables: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_le_bytes().to_vec();
+        bytes.extend([0; 12]);
+        bytes.extend(header_size.to_le_bytes());
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(bits_per_pixel.to_le_bytes());
+        bytes.extend(compression.to_le_bytes());
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend([0; 16]);
+        bytes.extend(tables);
+        bytes.resize(
+            bytes
+                .len()
+                .max(FILE_HEADER_SIZE as usize + header_size as usize),
+            0,
+        );
+
+        let offset = bytes.len() as u32;
+        bytes[10..14].copy_from_slice(&offset.to_le_bytes());
+        bytes.extend(data);
+        let file_size = bytes.len() as u32;
+        bytes[2..6].copy_from_slice(&file_size.to_le_bytes());
+        bytes
+    }
+
+    fn load(bytes: &[u8]) -> Result<B2DO, BmpError> {
+        load_bmp_from_bytes(bytes, Dither::None)
+    }
+
+    // Blue, green, red and white in BGRX, the rest is black
+    fn palette(bits_per_pixel: u16) -> Vec<u8> {
+        let mut palette = vec![255, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 0, 255, 255, 255, 0];
+        palette.resize(4 << bits_per_pixel, 0);
+        palette
+    }
 
-    array[0] = (shifted >> 0) as u8;
-    array[1] = (shifted >> 8) as u8;
-    array[2] = (shifted >> 16) as u8;
-    array[3] = (shifted >> 24) as u8;
+    #[test]
+    fn loads_24_bit_bottom_up() {
+        let data = [
+            0, 0, 0, 255, 255, 255, 0, 0, 255, 0, 255, 0, //
+            255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0,
+        ];
+        let image = load(&bmp((4, 2), 24, COMPRESSION_RGB, 40, &[], &data)).unwrap();
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(image.bitmap, [BLUE, GREEN, RED, 0, 0, WHITE, RED, GREEN]);
+    }
+
+    #[test]
+    fn rejects_broken_headers() {
+        let mut bytes = bmp((4, 1), 24, COMPRESSION_RGB, 40, &[], &[0; 12]);
+        bytes[0] = b'X';
+        assert!(matches!(load(&bytes), Err(BmpError::InvalidSignature(_))));
+
+        let bytes = bmp((4, 1), 2, COMPRESSION_RGB, 40, &[], &[0; 12]);
+        assert!(matches!(
+            load(&bytes),
+            Err(BmpError::UnsupportedBitDepth(2))
+        ));
+
+        let bytes = bmp((4, 1), 24, 7, 40, &[], &[0; 12]);
+        assert!(matches!(
+            load(&bytes),
+            Err(BmpError::UnsupportedCompression(7))
+        ));
+
+        assert!(matches!(load(&bytes[..20]), Err(BmpError::Io(_))));
+    }
+
+    #[test]
+    fn truncated_pixel_data_is_an_error() {
+        let bytes = bmp((4, 4), 24, COMPRESSION_RGB, 40, &[], &[0; 20]);
+        assert!(matches!(
+            load(&bytes),
+            Err(BmpError::TruncatedPixelData {
+                expected: 48,
+                actual: 20
+            })
+        ));
+
+        // The header alone must not be able to ask for a huge buffer
+        let bytes = bmp((i32::MAX, i32::MAX), 24, COMPRESSION_RGB, 40, &[], &[0; 12]);
+        assert!(load(&bytes).is_err());
+    }
+
+    #[test]
+    fn loads_paletted_images() {
+        let data = [0b1010_0000, 0, 0, 0];
+        let image = load(&bmp((8, 1), 1, COMPRESSION_RGB, 40, &palette(1), &data)).unwrap();
+        assert_eq!(
+            image.bitmap,
+            [GREEN, BLUE, GREEN, BLUE, BLUE, BLUE, BLUE, BLUE]
+        );
+
+        let data = [0x12, 0x30, 0, 0];
+        let image = load(&bmp((3, 1), 4, COMPRESSION_RGB, 40, &palette(4), &data)).unwrap();
+        assert_eq!(image.bitmap, [GREEN, RED, WHITE]);
+
+        let data = [0, 1, 2, 3, 3, 2, 1, 0];
+        let image = load(&bmp((4, 2), 8, COMPRESSION_RGB, 40, &palette(8), &data)).unwrap();
+        assert_eq!(
+            image.bitmap,
+            [WHITE, RED, GREEN, BLUE, BLUE, GREEN, RED, WHITE]
+        );
+    }
+
+    #[test]
+    fn loads_16_bit_as_555() {
+        let data = [0x00, 0x7C, 0x1F, 0x00];
+        let image = load(&bmp((2, 1), 16, COMPRESSION_RGB, 40, &[], &data)).unwrap();
+        assert_eq!(image.bitmap, [RED, BLUE]);
+    }
+
+    #[test]
+    fn decodes_rle8() {
+        // Delta to (1, 0), a run of two, end of line, an absolute run with padding, end of bitmap
+        let data = [0, 2, 1, 0, 2, 1, 0, 0, 0, 3, 2, 3, 0, 0, 0, 1];
+        let image = load(&bmp((4, 2), 8, COMPRESSION_RLE8, 40, &palette(8), &data)).unwrap();
+        assert_eq!(
+            image.bitmap,
+            [RED, WHITE, BLUE, MASK_COLOR, MASK_COLOR, GREEN, GREEN, MASK_COLOR]
+        );
+    }
+
+    #[test]
+    fn decodes_rle4() {
+        // A run alternating two nibbles, then an absolute run of three nibbles
+        let data = [4, 0x12, 0, 3, 0x30, 0x10, 0, 1];
+        let image = load(&bmp((7, 1), 4, COMPRESSION_RLE4, 40, &palette(4), &data)).unwrap();
+        assert_eq!(image.bitmap, [GREEN, RED, GREEN, RED, WHITE, BLUE, GREEN]);
+    }
+
+    #[test]
+    fn rejects_broken_rle() {
+        let bytes = bmp((4, 1), 8, COMPRESSION_RLE8, 40, &palette(8), &[4]);
+        assert!(matches!(
+            load(&bytes),
+            Err(BmpError::TruncatedPixelData { .. })
+        ));
+
+        let bytes = bmp((4, 1), 8, COMPRESSION_RLE4, 40, &palette(8), &[0, 1]);
+        assert!(matches!(
+            load(&bytes),
+            Err(BmpError::UnsupportedFormat { .. })
+        ));
+
+        let bytes = bmp((i32::MAX, 2), 8, COMPRESSION_RLE8, 40, &palette(8), &[0, 1]);
+        assert!(matches!(
+            load(&bytes),
+            Err(BmpError::InvalidDimensions { .. })
+        ));
+    }
+
+    fn masks(masks: &[u32]) -> Vec<u8> {
+        Vec::from_iter(masks.iter().flat_map(|mask| mask.to_le_bytes()))
+    }
+
+    #[test]
+    fn honors_bitfield_masks() {
+        let tables = masks(&[0xF800, 0x07E0, 0x001F]);
+        let data = [0x00, 0xF8, 0xE0, 0x07];
+        let image = load(&bmp((2, 1), 16, COMPRESSION_BITFIELDS, 40, &tables, &data)).unwrap();
+        assert_eq!(image.bitmap, [RED, GREEN]);
+
+        // V4 headers carry an alpha mask
+        let tables = masks(&[0xFF0000, 0xFF00, 0xFF, 0xFF000000]);
+        let data = [0, 0, 255, 255, 255, 0, 0, 0];
+        let image = load(&bmp((2, 1), 32, COMPRESSION_BITFIELDS, 108, &tables, &data)).unwrap();
+        assert_eq!(image.bitmap, [RED, MASK_COLOR]);
+
+        // Without bitfields the fourth byte is padding
+        let image = load(&bmp((2, 1), 32, COMPRESSION_RGB, 40, &[], &data)).unwrap();
+        assert_eq!(image.bitmap, [RED, BLUE]);
+    }
+
+    #[test]
+    fn loads_top_down_images() {
+        let data = [0, 0, 255, 0, 255, 0, 0, 0];
+        let image = load(&bmp((1, -2), 24, COMPRESSION_RGB, 40, &[], &data)).unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_eq!(image.bitmap, [RED, BLUE]);
+    }
+
+    #[test]
+    fn skips_row_padding() {
+        let data = [
+            255, 0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9, //
+            255, 255, 255, 0, 0, 0, 255, 255, 255, 9, 9, 9,
+        ];
+        let image = load(&bmp((3, 2), 24, COMPRESSION_RGB, 40, &[], &data)).unwrap();
+        assert_eq!(image.bitmap, [WHITE, 0, WHITE, BLUE, GREEN, RED]);
+
+        let data = [0xFF, 0x80, 0x7F, 0xFF, 0x00, 0x00, 0x00, 0x00];
+        let image = load(&bmp((9, 2), 1, COMPRESSION_RGB, 40, &palette(1), &data)).unwrap();
+        assert_eq!(image.bitmap[..9], [BLUE; 9]);
+        assert_eq!(image.bitmap[9..], [GREEN; 9]);
+    }
 }
@@ -0,0 +1,447 @@
+use std::{error::Error, fmt::Display};
+
+const MAX_BITS: usize = 15;
+const END_OF_BLOCK: u16 = 256;
+
+const BLOCK_STORED: u32 = 0;
+const BLOCK_FIXED: u32 = 1;
+const BLOCK_DYNAMIC: u32 = 2;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug)]
+pub enum InflateError {
+    InvalidZlibHeader,
+    InvalidBlockType,
+    InvalidStoredLength,
+    InvalidCode,
+    InvalidDistance,
+    UnexpectedEnd,
+    ChecksumMismatch,
+    OutputTooLarge(usize),
+}
+
+impl Display for InflateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InflateError::InvalidZlibHeader => write!(f, "[Inflate] Invalid zlib header!"),
+            InflateError::InvalidBlockType => write!(f, "[Inflate] Invalid block type!"),
+            InflateError::InvalidStoredLength => {
+                write!(f, "[Inflate] Stored block length doesn't match!")
+            }
+            InflateError::InvalidCode => write!(f, "[Inflate] Invalid Huffman code!"),
+            InflateError::InvalidDistance => write!(f, "[Inflate] Distance is too far back!"),
+            InflateError::UnexpectedEnd => write!(f, "[Inflate] Unexpected end of stream!"),
+            InflateError::ChecksumMismatch => write!(f, "[Inflate] Adler-32 checksum mismatch!"),
+            InflateError::OutputTooLarge(limit) => {
+                write!(f, "[Inflate] Output is larger than {} bytes!", limit)
+            }
+        }
+    }
+}
+
+impl Error for InflateError {}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, InflateError> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or(InflateError::UnexpectedEnd)?;
+            self.position += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], InflateError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or(InflateError::UnexpectedEnd)?;
+        self.position += count;
+        Ok(bytes)
+    }
+}
+
+// Canonical Huffman table stored as code counts per length plus symbols sorted by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, InflateError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(InflateError::InvalidCode)
+    }
+}
+
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    if data.len() < 6 {
+        return Err(InflateError::UnexpectedEnd);
+    }
+
+    let cmf = data[0];
+    let flg = data[1];
+    if cmf & 0x0F != 8 || cmf >> 4 > 7 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err(InflateError::InvalidZlibHeader);
+    }
+    // Preset dictionaries are never used by PNG
+    if flg & 0x20 != 0 {
+        return Err(InflateError::InvalidZlibHeader);
+    }
+
+    let (output, consumed) = inflate(&data[2..], limit)?;
+
+    let checksum = data
+        .get(2 + consumed..2 + consumed + 4)
+        .ok_or(InflateError::UnexpectedEnd)?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&output)
+    {
+        return Err(InflateError::ChecksumMismatch);
+    }
+
+    Ok(output)
+}
+
+// Returns the decompressed bytes and the amount of input consumed
+// Output past the limit is an error, a few bytes of input can otherwise expand to gigabytes
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), InflateError> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::with_capacity(data.len().saturating_mul(4).min(limit));
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            BLOCK_STORED => inflate_stored(&mut reader, &mut output, limit)?,
+            BLOCK_FIXED => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut output, limit, &literals, &distances)?;
+            }
+            BLOCK_DYNAMIC => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut output, limit, &literals, &distances)?;
+            }
+            _ => return Err(InflateError::InvalidBlockType),
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    Ok((output, reader.position))
+}
+
+fn inflate_stored(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), InflateError> {
+    reader.align_to_byte();
+
+    let header = reader.bytes(4)?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let length_complement = u16::from_le_bytes([header[2], header[3]]);
+    if length != !length_complement {
+        return Err(InflateError::InvalidStoredLength);
+    }
+    if output.len() + length as usize > limit {
+        return Err(InflateError::OutputTooLarge(limit));
+    }
+
+    output.extend_from_slice(reader.bytes(length as usize)?);
+    Ok(())
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = literals.decode(reader)?;
+
+        if symbol < END_OF_BLOCK {
+            if output.len() >= limit {
+                return Err(InflateError::OutputTooLarge(limit));
+            }
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+
+        let symbol = (symbol - END_OF_BLOCK - 1) as usize;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(InflateError::InvalidCode);
+        }
+        let length =
+            LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = distances.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(InflateError::InvalidCode);
+        }
+        let distance =
+            DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+        if distance > output.len() {
+            return Err(InflateError::InvalidDistance);
+        }
+        if output.len() + length > limit {
+            return Err(InflateError::OutputTooLarge(limit));
+        }
+
+        // Byte by byte since the copy can overlap the bytes it produces
+        let start = output.len() - distance;
+        for i in 0..length {
+            output.push(output[start + i]);
+        }
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = vec![0; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(InflateError::InvalidCode);
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            18 => (0, 11 + reader.bits(7)? as usize),
+            _ => return Err(InflateError::InvalidCode),
+        };
+
+        if index + repeat > lengths.len() {
+            return Err(InflateError::InvalidCode);
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(InflateError::InvalidCode);
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fixed Huffman codes, back references included
+    const HELLO: [u8; 16] = [
+        120, 218, 203, 72, 205, 201, 201, 87, 200, 64, 39, 1, 104, 3, 8, 177,
+    ];
+
+    // 100000 zero bytes, dynamic Huffman codes and long matches
+    fn zeros() -> Vec<u8> {
+        let mut bytes = vec![
+            120, 218, 237, 193, 49, 1, 0, 0, 0, 194, 160, 245, 79, 109, 13, 15, 160,
+        ];
+        bytes.resize(113, 0);
+        bytes.extend([128, 87, 3, 134, 175, 0, 1]);
+        bytes
+    }
+
+    fn stored(data: &[u8]) -> Vec<u8> {
+        let length = data.len() as u16;
+        let mut bytes = vec![0x78, 0x01, 0x01];
+        bytes.extend(length.to_le_bytes());
+        bytes.extend((!length).to_le_bytes());
+        bytes.extend(data);
+        bytes.extend(adler32(data).to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decompresses_every_block_type() {
+        assert_eq!(zlib_decompress(&stored(b"abc"), 3).unwrap(), b"abc");
+        assert_eq!(
+            zlib_decompress(&HELLO, 100).unwrap(),
+            b"hello hello hello hello"
+        );
+        assert_eq!(zlib_decompress(&zeros(), 100000).unwrap(), [0; 100000]);
+    }
+
+    #[test]
+    fn stops_at_the_output_limit() {
+        assert!(matches!(
+            zlib_decompress(&zeros(), 99999),
+            Err(InflateError::OutputTooLarge(99999))
+        ));
+        assert!(matches!(
+            zlib_decompress(&HELLO, 4),
+            Err(InflateError::OutputTooLarge(4))
+        ));
+        assert!(matches!(
+            zlib_decompress(&stored(b"abc"), 2),
+            Err(InflateError::OutputTooLarge(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_streams() {
+        let mut bytes = HELLO;
+        bytes[1] = 0;
+        assert!(matches!(
+            zlib_decompress(&bytes, 100),
+            Err(InflateError::InvalidZlibHeader)
+        ));
+
+        let mut bytes = HELLO;
+        bytes[15] ^= 1;
+        assert!(matches!(
+            zlib_decompress(&bytes, 100),
+            Err(InflateError::ChecksumMismatch)
+        ));
+
+        assert!(matches!(
+            zlib_decompress(&HELLO[..10], 100),
+            Err(InflateError::UnexpectedEnd)
+        ));
+
+        assert!(matches!(
+            zlib_decompress(&[0x78, 0x01, 0x07, 0, 0, 0], 100),
+            Err(InflateError::InvalidBlockType)
+        ));
+
+        let mut bytes = stored(b"abc");
+        bytes[5] = 0;
+        assert!(matches!(
+            zlib_decompress(&bytes, 100),
+            Err(InflateError::InvalidStoredLength)
+        ));
+    }
+}
@@ -1 +1,114 @@
 pub mod bmp;
+pub mod inflate;
+pub mod pcx;
+pub mod png;
+pub mod tga;
+
+use std::{error::Error, fmt::Display, fs, io};
+
+use crate::{
+    buffer2d::{dither::Dither, B2DO},
+    utils::color_from_tuple,
+};
+
+use self::{bmp::BmpError, pcx::PcxError, png::PngError, tga::TgaError};
+
+pub const ALPHA_THRESHOLD: u8 = 128;
+// 8192x8192, anything larger is taken to be a broken or hostile header
+pub const MAX_IMAGE_PIXELS: usize = 1 << 26;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+    Pcx,
+    Tga,
+}
+
+impl ImageFormat {
+    // TGA has no signature, so it is only assumed once everything else fails
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"BM") {
+            Some(ImageFormat::Bmp)
+        } else if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+            Some(ImageFormat::Png)
+        } else if bytes.len() > 2 && bytes[0] == pcx::MANUFACTURER && bytes[2] <= 1 {
+            Some(ImageFormat::Pcx)
+        } else if tga::is_tga(bytes) {
+            Some(ImageFormat::Tga)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    UnknownFormat,
+    Bmp(BmpError),
+    Png(PngError),
+    Pcx(PcxError),
+    Tga(TgaError),
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::Io(error) => write!(f, "[Image] IO error: {}", error),
+            ImageError::UnknownFormat => write!(f, "[Image] Unknown image format!"),
+            ImageError::Bmp(error) => error.fmt(f),
+            ImageError::Png(error) => error.fmt(f),
+            ImageError::Pcx(error) => error.fmt(f),
+            ImageError::Tga(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageError::Io(error) => Some(error),
+            ImageError::UnknownFormat => None,
+            ImageError::Bmp(error) => Some(error),
+            ImageError::Png(error) => Some(error),
+            ImageError::Pcx(error) => Some(error),
+            ImageError::Tga(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> Self {
+        ImageError::Io(error)
+    }
+}
+
+pub fn load_image(path: &str, dither: Dither) -> Result<B2DO, ImageError> {
+    load_image_from_bytes(&fs::read(path)?, dither)
+}
+
+pub fn load_image_from_bytes(bytes: &[u8], dither: Dither) -> Result<B2DO, ImageError> {
+    match ImageFormat::detect(bytes) {
+        Some(ImageFormat::Bmp) => bmp::load_bmp_from_bytes(bytes, dither).map_err(ImageError::Bmp),
+        Some(ImageFormat::Png) => png::load_png_from_bytes(bytes, dither).map_err(ImageError::Png),
+        Some(ImageFormat::Pcx) => pcx::load_pcx_from_bytes(bytes, dither).map_err(ImageError::Pcx),
+        Some(ImageFormat::Tga) => tga::load_tga_from_bytes(bytes, dither).map_err(ImageError::Tga),
+        None => Err(ImageError::UnknownFormat),
+    }
+}
+
+// Compressed formats can claim sizes the data never backs, so buffers are capped before allocating
+pub(crate) fn checked_pixel_count(width: usize, height: usize) -> Option<usize> {
+    width
+        .checked_mul(height)
+        .filter(|count| *count <= MAX_IMAGE_PIXELS)
+}
+
+pub(crate) fn color_from_rgb8(r: u8, g: u8, b: u8) -> u16 {
+    color_from_tuple((
+        (r as f32 / 256.0 * 32.0) as u16,
+        (g as f32 / 256.0 * 32.0) as u16,
+        (b as f32 / 256.0 * 32.0) as u16,
+    ))
+}
@@ -0,0 +1,443 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::buffer2d::{dither::Dither, B2DO, MASK_COLOR};
+
+use super::{checked_pixel_count, color_from_rgb8, ALPHA_THRESHOLD};
+
+pub(super) const MANUFACTURER: u8 = 0x0A;
+const HEADER_SIZE: usize = 128;
+
+const ENCODING_NONE: u8 = 0;
+const ENCODING_RLE: u8 = 1;
+
+const RLE_MARKER: u8 = 0xC0;
+const RLE_COUNT_MASK: u8 = 0x3F;
+
+const VGA_PALETTE_MARKER: u8 = 0x0C;
+const VGA_PALETTE_SIZE: usize = 256 * 3;
+
+#[derive(Debug)]
+pub enum PcxError {
+    Io(io::Error),
+    InvalidManufacturer(u8),
+    UnsupportedEncoding(u8),
+    UnsupportedFormat { bits_per_pixel: u8, planes: u8 },
+    InvalidDimensions,
+    MissingPalette,
+    TruncatedPixelData { expected: usize, actual: usize },
+}
+
+impl Display for PcxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PcxError::Io(error) => write!(f, "[PCX] IO error: {}", error),
+            PcxError::InvalidManufacturer(manufacturer) => {
+                write!(f, "[PCX] Manufacturer {:#04x} didn't match!", manufacturer)
+            }
+            PcxError::UnsupportedEncoding(encoding) => {
+                write!(f, "[PCX] Encoding {} is not supported!", encoding)
+            }
+            PcxError::UnsupportedFormat {
+                bits_per_pixel,
+                planes,
+            } => write!(
+                f,
+                "[PCX] Unsupported combination of {} bits per pixel and {} planes!",
+                bits_per_pixel, planes
+            ),
+            PcxError::InvalidDimensions => write!(f, "[PCX] Invalid image dimensions!"),
+            PcxError::MissingPalette => write!(f, "[PCX] 256 color palette is missing!"),
+            PcxError::TruncatedPixelData { expected, actual } => write!(
+                f,
+                "[PCX] Pixel data is truncated ({} of {} bytes)!",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl Error for PcxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PcxError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PcxError {
+    fn from(error: io::Error) -> Self {
+        PcxError::Io(error)
+    }
+}
+
+struct PcxHeader {
+    encoding: u8,
+    bits_per_pixel: u8,
+    planes: u8,
+    bytes_per_line: usize,
+    width: usize,
+    height: usize,
+    ega_palette: [[u8; 3]; 16],
+}
+
+pub fn load_pcx(path: &str, dither: Dither) -> Result<B2DO, PcxError> {
+    let mut f = File::open(path)?;
+    load_pcx_from_reader(&mut f, dither)
+}
+
+pub fn load_pcx_from_reader<R: Read>(reader: &mut R, dither: Dither) -> Result<B2DO, PcxError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    load_pcx_from_bytes(&bytes, dither)
+}
+
+pub fn load_pcx_from_bytes(bytes: &[u8], dither: Dither) -> Result<B2DO, PcxError> {
+    let header = read_header(bytes)?;
+
+    let palette = match (header.bits_per_pixel, header.planes) {
+        (8, 1) => read_vga_palette(bytes)?,
+        (1, 1..=4) | (2 | 4, 1) => header.ega_palette.to_vec(),
+        (8, 3 | 4) => vec![],
+        (bits_per_pixel, planes) => {
+            return Err(PcxError::UnsupportedFormat {
+                bits_per_pixel,
+                planes,
+            })
+        }
+    };
+
+    let scanline_size = header.bytes_per_line * header.planes as usize;
+    let scanlines = decode_scanlines(&bytes[HEADER_SIZE..], &header, scanline_size)?;
+
+    let mut pixels = Vec::with_capacity(header.width * header.height);
+    for (y, scanline) in scanlines.chunks_exact(scanline_size).enumerate() {
+        let planes = Vec::from_iter(scanline.chunks_exact(header.bytes_per_line));
+        for x in 0..header.width {
+            pixels.push(pixel_color(&planes, (x, y), &header, &palette, dither));
+        }
+    }
+
+    Ok(B2DO {
+        width: header.width as i32,
+        height: header.height as i32,
+        stride: header.width as i32,
+        bitmap: pixels,
+    })
+}
+
+fn read_header(bytes: &[u8]) -> Result<PcxHeader, PcxError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(PcxError::TruncatedPixelData {
+            expected: HEADER_SIZE,
+            actual: bytes.len(),
+        });
+    }
+
+    if bytes[0] != MANUFACTURER {
+        return Err(PcxError::InvalidManufacturer(bytes[0]));
+    }
+
+    let encoding = bytes[2];
+    if encoding != ENCODING_NONE && encoding != ENCODING_RLE {
+        return Err(PcxError::UnsupportedEncoding(encoding));
+    }
+
+    let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let (x_min, y_min, x_max, y_max) = (read_u16(4), read_u16(6), read_u16(8), read_u16(10));
+    if x_max < x_min || y_max < y_min {
+        return Err(PcxError::InvalidDimensions);
+    }
+
+    let mut ega_palette = [[0; 3]; 16];
+    for (i, entry) in ega_palette.iter_mut().enumerate() {
+        entry.copy_from_slice(&bytes[16 + i * 3..16 + i * 3 + 3]);
+    }
+
+    let header = PcxHeader {
+        encoding,
+        bits_per_pixel: bytes[3],
+        planes: bytes[65],
+        bytes_per_line: read_u16(66) as usize,
+        width: (x_max - x_min) as usize + 1,
+        height: (y_max - y_min) as usize + 1,
+        ega_palette,
+    };
+
+    if checked_pixel_count(header.width, header.height).is_none()
+        || header.bytes_per_line * 8 < header.width * header.bits_per_pixel as usize
+    {
+        return Err(PcxError::InvalidDimensions);
+    }
+
+    Ok(header)
+}
+
+fn read_vga_palette(bytes: &[u8]) -> Result<Vec<[u8; 3]>, PcxError> {
+    if bytes.len() < HEADER_SIZE + VGA_PALETTE_SIZE + 1 {
+        return Err(PcxError::MissingPalette);
+    }
+
+    let palette_start = bytes.len() - VGA_PALETTE_SIZE;
+    if bytes[palette_start - 1] != VGA_PALETTE_MARKER {
+        return Err(PcxError::MissingPalette);
+    }
+
+    Ok(Vec::from_iter(
+        bytes[palette_start..]
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]]),
+    ))
+}
+
+// Runs are allowed to cross plane boundaries, so every scanline is decoded as one stream
+fn decode_scanlines(
+    data: &[u8],
+    header: &PcxHeader,
+    scanline_size: usize,
+) -> Result<Vec<u8>, PcxError> {
+    let expected = scanline_size
+        .checked_mul(header.height)
+        .ok_or(PcxError::InvalidDimensions)?;
+    let truncated = |actual| PcxError::TruncatedPixelData { expected, actual };
+
+    if header.encoding == ENCODING_NONE {
+        return match data.get(..expected) {
+            Some(scanlines) => Ok(scanlines.to_vec()),
+            None => Err(truncated(data.len())),
+        };
+    }
+
+    // No more is reserved than the runs could expand to, a short body can't claim a huge buffer
+    let max_expansion = RLE_COUNT_MASK as usize;
+    let mut scanlines = Vec::with_capacity(expected.min(data.len().saturating_mul(max_expansion)));
+    let mut position = 0;
+    while scanlines.len() < expected {
+        let byte = *data
+            .get(position)
+            .ok_or_else(|| truncated(scanlines.len()))?;
+        position += 1;
+
+        if byte & RLE_MARKER == RLE_MARKER {
+            let count = (byte & RLE_COUNT_MASK) as usize;
+            let value = *data
+                .get(position)
+                .ok_or_else(|| truncated(scanlines.len()))?;
+            position += 1;
+            let count = count.min(expected - scanlines.len());
+            scanlines.extend(std::iter::repeat_n(value, count));
+        } else {
+            scanlines.push(byte);
+        }
+    }
+
+    Ok(scanlines)
+}
+
+// Only true color pixels are dithered, palettes already are what the artist picked
+fn pixel_color(
+    planes: &[&[u8]],
+    (x, y): (usize, usize),
+    header: &PcxHeader,
+    palette: &[[u8; 3]],
+    dither: Dither,
+) -> u16 {
+    let truecolor =
+        || dither.color_from_rgb8(planes[0][x], planes[1][x], planes[2][x], x as i32, y as i32);
+
+    match (header.bits_per_pixel, header.planes) {
+        (8, 3) => truecolor(),
+        (8, 4) => {
+            if planes[3][x] < ALPHA_THRESHOLD {
+                MASK_COLOR
+            } else {
+                truecolor()
+            }
+        }
+        (8, _) => palette_color(palette, planes[0][x] as usize),
+        (1, _) => {
+            // Each plane contributes one bit of the 16 color palette index
+            let index = planes.iter().enumerate().fold(0, |index, (plane, line)| {
+                let bit = (line[x / 8] >> (7 - x % 8)) & 1;
+                index | ((bit as usize) << plane)
+            });
+            palette_color(palette, index)
+        }
+        (bits_per_pixel, _) => {
+            let bits_per_pixel = bits_per_pixel as usize;
+            let bit = x * bits_per_pixel;
+            let shift = 8 - bits_per_pixel - (bit % 8);
+            let index = (planes[0][bit / 8] >> shift) as usize & ((1 << bits_per_pixel) - 1);
+            palette_color(palette, index)
+        }
+    }
+}
+
+fn palette_color(palette: &[[u8; 3]], index: usize) -> u16 {
+    match palette.get(index) {
+        Some([r, g, b]) => color_from_rgb8(*r, *g, *b),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 31 << 10;
+    const GREEN: u16 = 31 << 5;
+    const BLUE: u16 = 31;
+    const WHITE: u16 = 0x7FFF;
+
+    // Red, green, blue and white, the rest is black
+    const PALETTE: [u8; 12] = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+
+    // The EGA palette is always filled in, the VGA one is up to the test
+    fn pcx(
+        (width, height): (u16, u16),
+        encoding: u8,
+        (bits_per_pixel, planes): (u8, u8),
+        bytes_per_line: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(&[MANUFACTURER, 5, encoding, bits_per_pixel]);
+        bytes[8..10].copy_from_slice(&(width - 1).to_le_bytes());
+        bytes[10..12].copy_from_slice(&(height - 1).to_le_bytes());
+        bytes[16..16 + PALETTE.len()].copy_from_slice(&PALETTE);
+        bytes[65] = planes;
+        bytes[66..68].copy_from_slice(&bytes_per_line.to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn with_vga_palette(mut bytes: Vec<u8>) -> Vec<u8> {
+        bytes.push(VGA_PALETTE_MARKER);
+        bytes.extend(PALETTE);
+        bytes.resize(bytes.len() + VGA_PALETTE_SIZE - PALETTE.len(), 0);
+        bytes
+    }
+
+    fn load(bytes: &[u8]) -> Result<B2DO, PcxError> {
+        load_pcx_from_bytes(bytes, Dither::None)
+    }
+
+    #[test]
+    fn loads_8_bit() {
+        let data = [0, 1, 2, 9, 3, 3, 3, 9];
+        let bytes = with_vga_palette(pcx((3, 2), ENCODING_NONE, (8, 1), 4, &data));
+        let image = load(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.bitmap, [RED, GREEN, BLUE, WHITE, WHITE, WHITE]);
+
+        // Literal values with both top bits set have to be written as runs of one
+        let data = [0, 1, 0xC1, 200, 9, 0xC3, 3, 9];
+        let bytes = with_vga_palette(pcx((3, 2), ENCODING_RLE, (8, 1), 4, &data));
+        assert_eq!(
+            load(&bytes).unwrap().bitmap,
+            [RED, GREEN, 0, WHITE, WHITE, WHITE]
+        );
+    }
+
+    #[test]
+    fn loads_truecolor_planes() {
+        let data = [255, 0, 0, 255, 0, 0];
+        let bytes = pcx((2, 1), ENCODING_NONE, (8, 3), 2, &data);
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, GREEN]);
+
+        // The zero run starts in the red plane and ends in the green one
+        let data = [0xC1, 255, 0xC2, 0, 0xC1, 255, 0xC2, 0];
+        let bytes = pcx((2, 1), ENCODING_RLE, (8, 3), 2, &data);
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, GREEN]);
+
+        let data = [255, 255, 0, 255, 0, 255, 255, 0];
+        let bytes = pcx((2, 1), ENCODING_NONE, (8, 4), 2, &data);
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, MASK_COLOR]);
+    }
+
+    #[test]
+    fn loads_ega() {
+        // Plane 0 holds bit 0 of each index and plane 1 bit 1
+        let data = [0b0101_0000, 0b0011_0000, 0, 0];
+        let bytes = pcx((5, 1), ENCODING_NONE, (1, 4), 1, &data);
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, GREEN, BLUE, WHITE, RED]);
+
+        let bytes = pcx((4, 1), ENCODING_NONE, (2, 1), 1, &[0b00_01_10_11]);
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, GREEN, BLUE, WHITE]);
+
+        let bytes = pcx((2, 1), ENCODING_NONE, (4, 1), 1, &[0x03]);
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, WHITE]);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let mut bytes = pcx((2, 1), ENCODING_NONE, (8, 3), 2, &[0; 6]);
+        bytes[0] = 0;
+        assert!(matches!(
+            load(&bytes),
+            Err(PcxError::InvalidManufacturer(0))
+        ));
+
+        let bytes = pcx((2, 1), 2, (8, 3), 2, &[0; 6]);
+        assert!(matches!(
+            load(&bytes),
+            Err(PcxError::UnsupportedEncoding(2))
+        ));
+
+        let bytes = pcx((2, 1), ENCODING_NONE, (8, 2), 2, &[0; 4]);
+        assert!(matches!(
+            load(&bytes),
+            Err(PcxError::UnsupportedFormat {
+                bits_per_pixel: 8,
+                planes: 2
+            })
+        ));
+
+        let bytes = pcx((2, 1), ENCODING_NONE, (8, 1), 2, &[0; 2]);
+        assert!(matches!(load(&bytes), Err(PcxError::MissingPalette)));
+
+        let bytes = pcx((4, 1), ENCODING_NONE, (8, 3), 2, &[0; 6]);
+        assert!(matches!(load(&bytes), Err(PcxError::InvalidDimensions)));
+
+        let bytes = pcx((u16::MAX, u16::MAX), ENCODING_NONE, (8, 3), u16::MAX, &[]);
+        assert!(matches!(load(&bytes), Err(PcxError::InvalidDimensions)));
+
+        let mut bytes = pcx((2, 1), ENCODING_NONE, (8, 3), 2, &[0; 6]);
+        bytes[4] = 5;
+        assert!(matches!(load(&bytes), Err(PcxError::InvalidDimensions)));
+    }
+
+    #[test]
+    fn rejects_truncated_pixel_data() {
+        let bytes = pcx((2, 1), ENCODING_NONE, (8, 3), 2, &[0; 5]);
+        assert!(matches!(
+            load(&bytes),
+            Err(PcxError::TruncatedPixelData {
+                expected: 6,
+                actual: 5
+            })
+        ));
+
+        let bytes = pcx((2, 1), ENCODING_RLE, (8, 3), 2, &[0xC4, 0, 0xC1]);
+        assert!(matches!(
+            load(&bytes),
+            Err(PcxError::TruncatedPixelData {
+                expected: 6,
+                actual: 4
+            })
+        ));
+
+        assert!(matches!(
+            load(&[MANUFACTURER; 16]),
+            Err(PcxError::TruncatedPixelData {
+                expected: HEADER_SIZE,
+                actual: 16
+            })
+        ));
+    }
+}
@@ -0,0 +1,892 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::buffer2d::{dither::Dither, B2DO, MASK_COLOR};
+
+use super::{
+    checked_pixel_count,
+    inflate::{zlib_decompress, InflateError},
+    ALPHA_THRESHOLD,
+};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+const COLOR_TYPE_GRAYSCALE: u8 = 0;
+const COLOR_TYPE_TRUECOLOR: u8 = 2;
+const COLOR_TYPE_INDEXED: u8 = 3;
+const COLOR_TYPE_GRAYSCALE_ALPHA: u8 = 4;
+const COLOR_TYPE_TRUECOLOR_ALPHA: u8 = 6;
+
+const FILTER_NONE: u8 = 0;
+const FILTER_SUB: u8 = 1;
+const FILTER_UP: u8 = 2;
+const FILTER_AVERAGE: u8 = 3;
+const FILTER_PAETH: u8 = 4;
+
+const INTERLACE_NONE: u8 = 0;
+const INTERLACE_ADAM7: u8 = 1;
+
+// (x start, y start, x step, y step) for each of the seven passes
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+#[derive(Debug)]
+pub enum PngError {
+    Io(io::Error),
+    InvalidSignature,
+    InvalidChunkCrc([u8; 4]),
+    TruncatedChunk,
+    MissingHeader,
+    MissingPalette,
+    MissingImageData,
+    UnsupportedColorType(u8),
+    UnsupportedBitDepth { color_type: u8, bit_depth: u8 },
+    UnsupportedCompression(u8),
+    UnsupportedFilterMethod(u8),
+    UnsupportedInterlace(u8),
+    InvalidFilterType(u8),
+    Inflate(InflateError),
+    TruncatedPixelData { expected: usize, actual: usize },
+    InvalidDimensions { width: usize, height: usize },
+}
+
+impl Display for PngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PngError::Io(error) => write!(f, "[PNG] IO error: {}", error),
+            PngError::InvalidSignature => write!(f, "[PNG] Signature didn't match!"),
+            PngError::InvalidChunkCrc(name) => write!(
+                f,
+                "[PNG] CRC of chunk \"{}\" didn't match!",
+                String::from_utf8_lossy(name)
+            ),
+            PngError::TruncatedChunk => write!(f, "[PNG] Chunk is truncated!"),
+            PngError::MissingHeader => write!(f, "[PNG] IHDR chunk is missing!"),
+            PngError::MissingPalette => write!(f, "[PNG] PLTE chunk is missing!"),
+            PngError::MissingImageData => write!(f, "[PNG] IDAT chunk is missing!"),
+            PngError::UnsupportedColorType(color_type) => {
+                write!(f, "[PNG] Unsupported color type {}!", color_type)
+            }
+            PngError::UnsupportedBitDepth {
+                color_type,
+                bit_depth,
+            } => write!(
+                f,
+                "[PNG] Bit depth {} is not allowed for color type {}!",
+                bit_depth, color_type
+            ),
+            PngError::UnsupportedCompression(compression) => {
+                write!(
+                    f,
+                    "[PNG] Compression method {} is not supported!",
+                    compression
+                )
+            }
+            PngError::UnsupportedFilterMethod(filter_method) => {
+                write!(f, "[PNG] Filter method {} is not supported!", filter_method)
+            }
+            PngError::UnsupportedInterlace(interlace) => {
+                write!(f, "[PNG] Interlace method {} is not supported!", interlace)
+            }
+            PngError::InvalidFilterType(filter_type) => {
+                write!(f, "[PNG] Invalid filter type {}!", filter_type)
+            }
+            PngError::Inflate(error) => write!(f, "[PNG] {}", error),
+            PngError::TruncatedPixelData { expected, actual } => write!(
+                f,
+                "[PNG] Pixel data is truncated ({} of {} bytes)!",
+                actual, expected
+            ),
+            PngError::InvalidDimensions { width, height } => {
+                write!(f, "[PNG] Invalid dimensions {}x{}!", width, height)
+            }
+        }
+    }
+}
+
+impl Error for PngError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PngError::Io(error) => Some(error),
+            PngError::Inflate(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PngError {
+    fn from(error: io::Error) -> Self {
+        PngError::Io(error)
+    }
+}
+
+impl From<InflateError> for PngError {
+    fn from(error: InflateError) -> Self {
+        PngError::Inflate(error)
+    }
+}
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlace: u8,
+}
+
+impl PngHeader {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_TRUECOLOR => 3,
+            COLOR_TYPE_GRAYSCALE_ALPHA => 2,
+            COLOR_TYPE_TRUECOLOR_ALPHA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    fn row_bytes(&self, width: usize) -> Option<usize> {
+        Some(width.checked_mul(self.bits_per_pixel())?.div_ceil(8))
+    }
+
+    // Every row carries a filter type byte in front of its pixels
+    fn filtered_size(&self, width: usize, height: usize) -> Option<usize> {
+        self.row_bytes(width)?.checked_add(1)?.checked_mul(height)
+    }
+
+    fn invalid_dimensions(&self) -> PngError {
+        PngError::InvalidDimensions {
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+// tRNS holds per-entry alphas for indexed images and a single color key otherwise
+enum Transparency {
+    None,
+    Palette(Vec<u8>),
+    Key([u16; 3]),
+}
+
+pub fn load_png(path: &str, dither: Dither) -> Result<B2DO, PngError> {
+    let mut f = File::open(path)?;
+    load_png_from_reader(&mut f, dither)
+}
+
+pub fn load_png_from_reader<R: Read>(reader: &mut R, dither: Dither) -> Result<B2DO, PngError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    load_png_from_bytes(&bytes, dither)
+}
+
+pub fn load_png_from_bytes(bytes: &[u8], dither: Dither) -> Result<B2DO, PngError> {
+    if bytes.len() < SIGNATURE.len() || bytes[..SIGNATURE.len()] != SIGNATURE {
+        return Err(PngError::InvalidSignature);
+    }
+
+    let mut header = None;
+    let mut palette = vec![];
+    let mut transparency = Transparency::None;
+    let mut compressed = vec![];
+
+    let mut position = SIGNATURE.len();
+    while position < bytes.len() {
+        let (name, data) = read_chunk(bytes, &mut position)?;
+
+        match &name {
+            b"IHDR" => header = Some(read_header(data)?),
+            b"PLTE" => palette = Vec::from_iter(data.chunks_exact(3).map(|c| [c[0], c[1], c[2]])),
+            b"tRNS" => {
+                transparency = match header.as_ref().map(|h| h.color_type) {
+                    Some(COLOR_TYPE_INDEXED) => Transparency::Palette(data.to_vec()),
+                    Some(COLOR_TYPE_GRAYSCALE) if data.len() >= 2 => {
+                        let gray = u16::from_be_bytes([data[0], data[1]]);
+                        Transparency::Key([gray, gray, gray])
+                    }
+                    Some(COLOR_TYPE_TRUECOLOR) if data.len() >= 6 => Transparency::Key([
+                        u16::from_be_bytes([data[0], data[1]]),
+                        u16::from_be_bytes([data[2], data[3]]),
+                        u16::from_be_bytes([data[4], data[5]]),
+                    ]),
+                    _ => Transparency::None,
+                }
+            }
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or(PngError::MissingHeader)?;
+    if header.color_type == COLOR_TYPE_INDEXED && palette.is_empty() {
+        return Err(PngError::MissingPalette);
+    }
+    if compressed.is_empty() {
+        return Err(PngError::MissingImageData);
+    }
+
+    // Both sizes come straight from IHDR, so they are checked before anything is inflated
+    let pixel_count = checked_pixel_count(header.width, header.height)
+        .ok_or_else(|| header.invalid_dimensions())?;
+    let passes = match header.interlace {
+        INTERLACE_ADAM7 => &ADAM7_PASSES[..],
+        _ => &[(0, 0, 1, 1)][..],
+    };
+    let mut expected = 0usize;
+    for placement in passes {
+        let (pass_width, pass_height) = pass_size(&header, *placement);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        expected = header
+            .filtered_size(pass_width, pass_height)
+            .and_then(|size| expected.checked_add(size))
+            .ok_or_else(|| header.invalid_dimensions())?;
+    }
+
+    let filtered = zlib_decompress(&compressed, expected)?;
+    if filtered.len() < expected {
+        return Err(PngError::TruncatedPixelData {
+            expected,
+            actual: filtered.len(),
+        });
+    }
+
+    let mut pixels = vec![MASK_COLOR; pixel_count];
+
+    match header.interlace {
+        INTERLACE_ADAM7 => {
+            let mut offset = 0;
+            for placement in ADAM7_PASSES {
+                let (x_start, y_start, x_step, y_step) = placement;
+                let (pass_width, pass_height) = pass_size(&header, placement);
+                if pass_width == 0 || pass_height == 0 {
+                    continue;
+                }
+
+                let pass = decode_pass(
+                    &filtered,
+                    &mut offset,
+                    &header,
+                    placement,
+                    &palette,
+                    &transparency,
+                    dither,
+                )?;
+
+                for (i, color) in pass.into_iter().enumerate() {
+                    let x = x_start + (i % pass_width) * x_step;
+                    let y = y_start + (i / pass_width) * y_step;
+                    pixels[y * header.width + x] = color;
+                }
+            }
+        }
+        _ => {
+            pixels = decode_pass(
+                &filtered,
+                &mut 0,
+                &header,
+                (0, 0, 1, 1),
+                &palette,
+                &transparency,
+                dither,
+            )?;
+        }
+    }
+
+    Ok(B2DO {
+        width: header.width as i32,
+        height: header.height as i32,
+        stride: header.width as i32,
+        bitmap: pixels,
+    })
+}
+
+fn read_chunk<'a>(bytes: &'a [u8], position: &mut usize) -> Result<([u8; 4], &'a [u8]), PngError> {
+    let length_and_name = bytes
+        .get(*position..*position + 8)
+        .ok_or(PngError::TruncatedChunk)?;
+    let length = u32::from_be_bytes([
+        length_and_name[0],
+        length_and_name[1],
+        length_and_name[2],
+        length_and_name[3],
+    ]) as usize;
+    let name = [
+        length_and_name[4],
+        length_and_name[5],
+        length_and_name[6],
+        length_and_name[7],
+    ];
+
+    let start = *position + 8;
+    let data = bytes
+        .get(start..start + length)
+        .ok_or(PngError::TruncatedChunk)?;
+    let crc = bytes
+        .get(start + length..start + length + 4)
+        .ok_or(PngError::TruncatedChunk)?;
+
+    if u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]])
+        != crc32(&bytes[*position + 4..start + length])
+    {
+        return Err(PngError::InvalidChunkCrc(name));
+    }
+
+    *position = start + length + 4;
+    Ok((name, data))
+}
+
+fn read_header(data: &[u8]) -> Result<PngHeader, PngError> {
+    if data.len() < 13 {
+        return Err(PngError::TruncatedChunk);
+    }
+
+    let header = PngHeader {
+        width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize,
+        height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize,
+        bit_depth: data[8],
+        color_type: data[9],
+        interlace: data[12],
+    };
+
+    let allowed_bit_depths: &[u8] = match header.color_type {
+        COLOR_TYPE_GRAYSCALE => &[1, 2, 4, 8, 16],
+        COLOR_TYPE_INDEXED => &[1, 2, 4, 8],
+        COLOR_TYPE_TRUECOLOR | COLOR_TYPE_GRAYSCALE_ALPHA | COLOR_TYPE_TRUECOLOR_ALPHA => &[8, 16],
+        color_type => return Err(PngError::UnsupportedColorType(color_type)),
+    };
+    if !allowed_bit_depths.contains(&header.bit_depth) {
+        return Err(PngError::UnsupportedBitDepth {
+            color_type: header.color_type,
+            bit_depth: header.bit_depth,
+        });
+    }
+
+    if data[10] != 0 {
+        return Err(PngError::UnsupportedCompression(data[10]));
+    }
+    if data[11] != 0 {
+        return Err(PngError::UnsupportedFilterMethod(data[11]));
+    }
+    if header.interlace != INTERLACE_NONE && header.interlace != INTERLACE_ADAM7 {
+        return Err(PngError::UnsupportedInterlace(header.interlace));
+    }
+
+    Ok(header)
+}
+
+// Placement is (x_start, y_start, x_step, y_step), a plain image is one pass of (0, 0, 1, 1)
+fn pass_size(header: &PngHeader, placement: (usize, usize, usize, usize)) -> (usize, usize) {
+    let (x_start, y_start, x_step, y_step) = placement;
+    (
+        (header.width + x_step - x_start - 1) / x_step,
+        (header.height + y_step - y_start - 1) / y_step,
+    )
+}
+
+fn decode_pass(
+    filtered: &[u8],
+    offset: &mut usize,
+    header: &PngHeader,
+    placement: (usize, usize, usize, usize),
+    palette: &[[u8; 3]],
+    transparency: &Transparency,
+    dither: Dither,
+) -> Result<Vec<u16>, PngError> {
+    let (x_start, y_start, x_step, y_step) = placement;
+    let (width, height) = pass_size(header, placement);
+    let row_bytes = header
+        .row_bytes(width)
+        .ok_or_else(|| header.invalid_dimensions())?;
+    let expected = header
+        .filtered_size(width, height)
+        .and_then(|size| offset.checked_add(size))
+        .ok_or_else(|| header.invalid_dimensions())?;
+    if filtered.len() < expected {
+        return Err(PngError::TruncatedPixelData {
+            expected,
+            actual: filtered.len(),
+        });
+    }
+
+    // Filters work on whole bytes, sub-byte pixels use a distance of one
+    let filter_distance = (header.bits_per_pixel() / 8).max(1);
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut previous = vec![0; row_bytes];
+    let mut current = vec![0; row_bytes];
+
+    for y in 0..height {
+        let filter_type = filtered[*offset];
+        current.copy_from_slice(&filtered[*offset + 1..*offset + 1 + row_bytes]);
+        *offset += row_bytes + 1;
+
+        unfilter(filter_type, &mut current, &previous, filter_distance)?;
+
+        for x in 0..width {
+            let position = (x_start + x * x_step, y_start + y * y_step);
+            let color = pixel_color(&current, x, header, palette, transparency);
+            pixels.push(match color {
+                Some((r, g, b)) => {
+                    dither.color_from_rgb8(r, g, b, position.0 as i32, position.1 as i32)
+                }
+                None => MASK_COLOR,
+            });
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    Ok(pixels)
+}
+
+fn unfilter(
+    filter_type: u8,
+    current: &mut [u8],
+    previous: &[u8],
+    distance: usize,
+) -> Result<(), PngError> {
+    match filter_type {
+        FILTER_NONE => {}
+        FILTER_SUB => {
+            for i in distance..current.len() {
+                current[i] = current[i].wrapping_add(current[i - distance]);
+            }
+        }
+        FILTER_UP => {
+            for i in 0..current.len() {
+                current[i] = current[i].wrapping_add(previous[i]);
+            }
+        }
+        FILTER_AVERAGE => {
+            for i in 0..current.len() {
+                let left = if i >= distance {
+                    current[i - distance]
+                } else {
+                    0
+                };
+                current[i] =
+                    current[i].wrapping_add(((left as u16 + previous[i] as u16) / 2) as u8);
+            }
+        }
+        FILTER_PAETH => {
+            for i in 0..current.len() {
+                let (left, upper_left) = if i >= distance {
+                    (current[i - distance], previous[i - distance])
+                } else {
+                    (0, 0)
+                };
+                current[i] = current[i].wrapping_add(paeth(left, previous[i], upper_left));
+            }
+        }
+        filter_type => return Err(PngError::InvalidFilterType(filter_type)),
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn pixel_color(
+    row: &[u8],
+    x: usize,
+    header: &PngHeader,
+    palette: &[[u8; 3]],
+    transparency: &Transparency,
+) -> Option<(u8, u8, u8)> {
+    let channels = header.channels();
+    let bit_depth = header.bit_depth;
+    let sample = |channel: usize| read_sample(row, x * channels + channel, bit_depth);
+    let scale = |value: u16| scale_to_8bit(value, bit_depth);
+
+    let (r, g, b, a) = match header.color_type {
+        COLOR_TYPE_INDEXED => {
+            let index = sample(0) as usize;
+            let [r, g, b] = palette.get(index).copied().unwrap_or([0, 0, 0]);
+            let a = match transparency {
+                Transparency::Palette(alphas) => alphas.get(index).copied().unwrap_or(255),
+                _ => 255,
+            };
+            (r, g, b, a)
+        }
+        COLOR_TYPE_GRAYSCALE => {
+            let gray = sample(0);
+            let a = match transparency {
+                Transparency::Key(key) if key[0] == gray => 0,
+                _ => 255,
+            };
+            (scale(gray), scale(gray), scale(gray), a)
+        }
+        COLOR_TYPE_GRAYSCALE_ALPHA => {
+            let gray = scale(sample(0));
+            (gray, gray, gray, scale(sample(1)))
+        }
+        COLOR_TYPE_TRUECOLOR => {
+            let rgb = [sample(0), sample(1), sample(2)];
+            let a = match transparency {
+                Transparency::Key(key) if *key == rgb => 0,
+                _ => 255,
+            };
+            (scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), a)
+        }
+        _ => (
+            scale(sample(0)),
+            scale(sample(1)),
+            scale(sample(2)),
+            scale(sample(3)),
+        ),
+    };
+
+    // Transparent pixels come back as None
+    (a >= ALPHA_THRESHOLD).then_some((r, g, b))
+}
+
+fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - (bit % 8);
+            ((row[bit / 8] >> shift) & ((1 << bit_depth) - 1)) as u16
+        }
+    }
+}
+
+fn scale_to_8bit(value: u16, bit_depth: u8) -> u8 {
+    match bit_depth {
+        16 => (value >> 8) as u8,
+        8 => value as u8,
+        _ => (value as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+    }
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::color_from_rgb8;
+
+    const RED: u16 = 31 << 10;
+    const GREEN: u16 = 31 << 5;
+    const BLUE: u16 = 31;
+    const WHITE: u16 = 0x7FFF;
+
+    fn gray(value: u8) -> u16 {
+        color_from_rgb8(value, value, value)
+    }
+
+    fn chunk(bytes: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) {
+        let start = bytes.len() + 4;
+        bytes.extend((data.len() as u32).to_be_bytes());
+        bytes.extend(name);
+        bytes.extend(data);
+        let crc = crc32(&bytes[start..]);
+        bytes.extend(crc.to_be_bytes());
+    }
+
+    // A single stored deflate block is all the decoder needs to see
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+            let a = (a + *byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        let length = data.len() as u16;
+        let mut bytes = vec![0x78, 0x01, 0x01];
+        bytes.extend(length.to_le_bytes());
+        bytes.extend((!length).to_le_bytes());
+        bytes.extend(data);
+        bytes.extend((b << 16 | a).to_be_bytes());
+        bytes
+    }
+
+    // Extra chunks go between IHDR and IDAT, filtered is the raw zlib payload
+    fn png(
+        (width, height): (u32, u32),
+        bit_depth: u8,
+        color_type: u8,
+        interlace: u8,
+        chunks: &[(&[u8; 4], &[u8])],
+        filtered: &[u8],
+    ) -> Vec<u8> {
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend(height.to_be_bytes());
+        header.extend([bit_depth, color_type, 0, 0, interlace]);
+
+        let mut bytes = SIGNATURE.to_vec();
+        chunk(&mut bytes, b"IHDR", &header);
+        for (name, data) in chunks {
+            chunk(&mut bytes, name, data);
+        }
+        chunk(&mut bytes, b"IDAT", &zlib(filtered));
+        chunk(&mut bytes, b"IEND", &[]);
+        bytes
+    }
+
+    fn load(bytes: &[u8]) -> Result<B2DO, PngError> {
+        load_png_from_bytes(bytes, Dither::None)
+    }
+
+    #[test]
+    fn undoes_every_filter() {
+        let filtered = [
+            [FILTER_NONE, 10, 20, 30],
+            [FILTER_SUB, 15, 10, 10],
+            [FILTER_UP, 5, 5, 5],
+            [FILTER_AVERAGE, 10, 5, 5],
+            [FILTER_PAETH, 0, 0, 0],
+        ]
+        .concat();
+        let bytes = png((3, 5), 8, COLOR_TYPE_GRAYSCALE, 0, &[], &filtered);
+        let image = load(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (3, 5));
+        assert_eq!(
+            image.bitmap,
+            Vec::from_iter([10, 20, 30, 15, 25, 35, 20, 30, 40, 20, 30, 40, 20, 30, 40].map(gray))
+        );
+    }
+
+    #[test]
+    fn loads_grayscale() {
+        let bytes = png((3, 1), 1, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 0b1010_0000]);
+        assert_eq!(load(&bytes).unwrap().bitmap, [WHITE, 0, WHITE]);
+
+        let bytes = png((2, 1), 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 0, 128]);
+        assert_eq!(load(&bytes).unwrap().bitmap, [0, gray(128)]);
+
+        let bytes = png(
+            (2, 1),
+            8,
+            COLOR_TYPE_GRAYSCALE,
+            0,
+            &[(b"tRNS", &[0, 128])],
+            &[0, 0, 128],
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [0, MASK_COLOR]);
+
+        let bytes = png(
+            (2, 1),
+            8,
+            COLOR_TYPE_GRAYSCALE_ALPHA,
+            0,
+            &[],
+            &[0, 255, 255, 0, 0],
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [WHITE, MASK_COLOR]);
+    }
+
+    #[test]
+    fn loads_truecolor() {
+        let bytes = png(
+            (2, 1),
+            8,
+            COLOR_TYPE_TRUECOLOR,
+            0,
+            &[],
+            &[0, 255, 0, 0, 0, 255, 0],
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, GREEN]);
+
+        let bytes = png(
+            (2, 1),
+            8,
+            COLOR_TYPE_TRUECOLOR,
+            0,
+            &[(b"tRNS", &[0, 0, 0, 255, 0, 0])],
+            &[0, 255, 0, 0, 0, 255, 0],
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, MASK_COLOR]);
+
+        let filtered = [
+            0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let bytes = png((2, 1), 16, COLOR_TYPE_TRUECOLOR, 0, &[], &filtered);
+        assert_eq!(load(&bytes).unwrap().bitmap, [BLUE, WHITE]);
+
+        let filtered = [0, 0, 0, 255, 255, 255, 255, 255, 0];
+        let bytes = png((2, 1), 8, COLOR_TYPE_TRUECOLOR_ALPHA, 0, &[], &filtered);
+        assert_eq!(load(&bytes).unwrap().bitmap, [BLUE, MASK_COLOR]);
+    }
+
+    #[test]
+    fn loads_indexed() {
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let bytes = png(
+            (5, 1),
+            2,
+            COLOR_TYPE_INDEXED,
+            0,
+            &[(b"PLTE", &palette), (b"tRNS", &[255, 0])],
+            &[0, 0b00_01_10_10, 0],
+        );
+        assert_eq!(
+            load(&bytes).unwrap().bitmap,
+            [RED, MASK_COLOR, BLUE, BLUE, RED]
+        );
+
+        let bytes = png((1, 1), 8, COLOR_TYPE_INDEXED, 0, &[], &[0, 0]);
+        assert!(matches!(load(&bytes), Err(PngError::MissingPalette)));
+    }
+
+    #[test]
+    fn loads_adam7() {
+        // Every pixel gets its own gray level so misplaced ones show up
+        let (width, height) = (5, 6);
+        let level = |x: usize, y: usize| ((y * width + x) * 8) as u8;
+
+        let mut filtered = vec![];
+        for (x_start, y_start, x_step, y_step) in ADAM7_PASSES {
+            if x_start >= width || y_start >= height {
+                continue;
+            }
+            for y in (y_start..height).step_by(y_step) {
+                filtered.push(FILTER_NONE);
+                filtered.extend((x_start..width).step_by(x_step).map(|x| level(x, y)));
+            }
+        }
+
+        let bytes = png(
+            (width as u32, height as u32),
+            8,
+            COLOR_TYPE_GRAYSCALE,
+            INTERLACE_ADAM7,
+            &[],
+            &filtered,
+        );
+        let image = load(&bytes).unwrap();
+        assert_eq!(
+            image.bitmap,
+            Vec::from_iter((0..width * height).map(|i| gray(level(i % width, i / width))))
+        );
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let bytes = png((1, 1), 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 0]);
+        assert!(matches!(load(&bytes[1..]), Err(PngError::InvalidSignature)));
+
+        let mut broken = bytes.clone();
+        broken[SIGNATURE.len() + 8] ^= 1;
+        assert!(matches!(
+            load(&broken),
+            Err(PngError::InvalidChunkCrc(name)) if name == *b"IHDR"
+        ));
+
+        let mut broken = SIGNATURE.to_vec();
+        chunk(&mut broken, b"IDAT", &zlib(&[0, 0]));
+        assert!(matches!(load(&broken), Err(PngError::MissingHeader)));
+
+        let bytes = png((2, 1), 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 0]);
+        assert!(matches!(
+            load(&bytes),
+            Err(PngError::TruncatedPixelData {
+                expected: 3,
+                actual: 2
+            })
+        ));
+
+        let bytes = png((1, 1), 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[5, 0]);
+        assert!(matches!(load(&bytes), Err(PngError::InvalidFilterType(5))));
+
+        let bytes = png((1 << 16, 1 << 16), 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 0]);
+        assert!(matches!(
+            load(&bytes),
+            Err(PngError::InvalidDimensions { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_zlib_streams() {
+        let with_idat = |idat: &[u8]| {
+            let mut bytes = SIGNATURE.to_vec();
+            chunk(
+                &mut bytes,
+                b"IHDR",
+                &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0],
+            );
+            chunk(&mut bytes, b"IDAT", idat);
+            chunk(&mut bytes, b"IEND", &[]);
+            bytes
+        };
+
+        let mut stream = zlib(&[0, 0]);
+        stream[1] = 0;
+        assert!(matches!(
+            load(&with_idat(&stream)),
+            Err(PngError::Inflate(InflateError::InvalidZlibHeader))
+        ));
+
+        let mut stream = zlib(&[0, 0]);
+        *stream.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            load(&with_idat(&stream)),
+            Err(PngError::Inflate(InflateError::ChecksumMismatch))
+        ));
+
+        let stream = zlib(&[0, 0]);
+        assert!(matches!(
+            load(&with_idat(&stream[..stream.len() - 2])),
+            Err(PngError::Inflate(InflateError::UnexpectedEnd))
+        ));
+
+        // A stream that inflates past the image is cut off rather than buffered
+        assert!(matches!(
+            load(&with_idat(&zlib(&[0; 64]))),
+            Err(PngError::Inflate(InflateError::OutputTooLarge(2)))
+        ));
+    }
+}
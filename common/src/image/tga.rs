@@ -0,0 +1,654 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::buffer2d::{dither::Dither, B2DO, MASK_COLOR};
+
+use super::{checked_pixel_count, ALPHA_THRESHOLD};
+
+const HEADER_SIZE: usize = 18;
+
+const IMAGE_TYPE_COLOR_MAPPED: u8 = 1;
+const IMAGE_TYPE_TRUECOLOR: u8 = 2;
+const IMAGE_TYPE_GRAYSCALE: u8 = 3;
+const IMAGE_TYPE_RLE_COLOR_MAPPED: u8 = 9;
+const IMAGE_TYPE_RLE_TRUECOLOR: u8 = 10;
+const IMAGE_TYPE_RLE_GRAYSCALE: u8 = 11;
+
+const DESCRIPTOR_ALPHA_BITS: u8 = 0x0F;
+const DESCRIPTOR_RIGHT_TO_LEFT: u8 = 0x10;
+const DESCRIPTOR_TOP_TO_BOTTOM: u8 = 0x20;
+
+const RLE_PACKET: u8 = 0x80;
+const RLE_COUNT_MASK: u8 = 0x7F;
+
+#[derive(Debug)]
+pub enum TgaError {
+    Io(io::Error),
+    UnsupportedImageType(u8),
+    UnsupportedPixelDepth { image_type: u8, pixel_depth: u8 },
+    UnsupportedColorMapDepth(u8),
+    MissingColorMap,
+    TruncatedPixelData { expected: usize, actual: usize },
+    InvalidDimensions { width: usize, height: usize },
+}
+
+impl Display for TgaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TgaError::Io(error) => write!(f, "[TGA] IO error: {}", error),
+            TgaError::UnsupportedImageType(image_type) => {
+                write!(f, "[TGA] Image type {} is not supported!", image_type)
+            }
+            TgaError::UnsupportedPixelDepth {
+                image_type,
+                pixel_depth,
+            } => write!(
+                f,
+                "[TGA] Pixel depth {} is not supported for image type {}!",
+                pixel_depth, image_type
+            ),
+            TgaError::UnsupportedColorMapDepth(depth) => {
+                write!(f, "[TGA] Color map depth {} is not supported!", depth)
+            }
+            TgaError::MissingColorMap => write!(f, "[TGA] Color map is missing!"),
+            TgaError::TruncatedPixelData { expected, actual } => write!(
+                f,
+                "[TGA] Pixel data is truncated ({} of {} bytes)!",
+                actual, expected
+            ),
+            TgaError::InvalidDimensions { width, height } => {
+                write!(f, "[TGA] Invalid dimensions {}x{}!", width, height)
+            }
+        }
+    }
+}
+
+impl Error for TgaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TgaError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TgaError {
+    fn from(error: io::Error) -> Self {
+        TgaError::Io(error)
+    }
+}
+
+struct TgaHeader {
+    id_length: usize,
+    color_map_type: u8,
+    image_type: u8,
+    color_map_first: usize,
+    color_map_length: usize,
+    color_map_depth: u8,
+    width: usize,
+    height: usize,
+    pixel_depth: u8,
+    descriptor: u8,
+}
+
+impl TgaHeader {
+    fn is_color_mapped(&self) -> bool {
+        matches!(
+            self.image_type,
+            IMAGE_TYPE_COLOR_MAPPED | IMAGE_TYPE_RLE_COLOR_MAPPED
+        )
+    }
+
+    fn is_grayscale(&self) -> bool {
+        matches!(
+            self.image_type,
+            IMAGE_TYPE_GRAYSCALE | IMAGE_TYPE_RLE_GRAYSCALE
+        )
+    }
+
+    fn is_rle(&self) -> bool {
+        self.image_type >= IMAGE_TYPE_RLE_COLOR_MAPPED
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.descriptor & DESCRIPTOR_ALPHA_BITS != 0
+    }
+}
+
+pub fn load_tga(path: &str, dither: Dither) -> Result<B2DO, TgaError> {
+    let mut f = File::open(path)?;
+    load_tga_from_reader(&mut f, dither)
+}
+
+pub fn load_tga_from_reader<R: Read>(reader: &mut R, dither: Dither) -> Result<B2DO, TgaError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    load_tga_from_bytes(&bytes, dither)
+}
+
+pub fn load_tga_from_bytes(bytes: &[u8], dither: Dither) -> Result<B2DO, TgaError> {
+    let header = read_header(bytes)?;
+
+    // Sizes come straight from the header, so they are checked before anything is allocated
+    let invalid_dimensions = || TgaError::InvalidDimensions {
+        width: header.width,
+        height: header.height,
+    };
+    let pixel_count =
+        checked_pixel_count(header.width, header.height).ok_or_else(invalid_dimensions)?;
+    let bytes_per_pixel = bytes_per_entry(header.pixel_depth);
+    let expected = pixel_count
+        .checked_mul(bytes_per_pixel)
+        .ok_or_else(invalid_dimensions)?;
+
+    let mut position = HEADER_SIZE + header.id_length;
+
+    let color_map = if header.color_map_type == 1 {
+        let entry_size = bytes_per_entry(header.color_map_depth);
+        let size = entry_size * header.color_map_length;
+        let data = bytes
+            .get(position..position + size)
+            .ok_or(TgaError::TruncatedPixelData {
+                expected: position + size,
+                actual: bytes.len(),
+            })?;
+        position += size;
+
+        Vec::from_iter(data.chunks_exact(entry_size).map(|c| {
+            truecolor(
+                c,
+                header.color_map_depth,
+                header.has_alpha(),
+                Dither::None,
+                (0, 0),
+            )
+        }))
+    } else {
+        vec![]
+    };
+
+    if header.is_color_mapped() && color_map.is_empty() {
+        return Err(TgaError::MissingColorMap);
+    }
+
+    let pixel_data = if header.is_rle() {
+        decode_rle(
+            &bytes[position.min(bytes.len())..],
+            expected,
+            bytes_per_pixel,
+        )?
+    } else {
+        position
+            .checked_add(expected)
+            .and_then(|end| bytes.get(position..end))
+            .ok_or(TgaError::TruncatedPixelData {
+                expected,
+                actual: bytes.len().saturating_sub(position),
+            })?
+            .to_vec()
+    };
+
+    // Dithering follows the storage order, the pattern only has to be regular
+    let colors = Vec::from_iter(pixel_data.chunks_exact(bytes_per_pixel).enumerate().map(
+        |(i, c)| {
+            let position = ((i % header.width) as i32, (i / header.width) as i32);
+            if header.is_color_mapped() {
+                let index = match bytes_per_pixel {
+                    1 => c[0] as usize,
+                    _ => u16::from_le_bytes([c[0], c[1]]) as usize,
+                };
+                index
+                    .checked_sub(header.color_map_first)
+                    .and_then(|index| color_map.get(index))
+                    .copied()
+                    .unwrap_or(0)
+            } else if header.is_grayscale() {
+                match bytes_per_pixel {
+                    2 if c[1] < ALPHA_THRESHOLD => MASK_COLOR,
+                    _ => dither.color_from_rgb8(c[0], c[0], c[0], position.0, position.1),
+                }
+            } else {
+                truecolor(c, header.pixel_depth, header.has_alpha(), dither, position)
+            }
+        },
+    ));
+
+    // Pixels are stored bottom-up and left-to-right unless the descriptor says otherwise
+    let mut pixels = Vec::with_capacity(pixel_count);
+    for y in 0..header.height {
+        let row = match header.descriptor & DESCRIPTOR_TOP_TO_BOTTOM {
+            0 => header.height - 1 - y,
+            _ => y,
+        };
+        let row = &colors[row * header.width..(row + 1) * header.width];
+        match header.descriptor & DESCRIPTOR_RIGHT_TO_LEFT {
+            0 => pixels.extend(row.iter()),
+            _ => pixels.extend(row.iter().rev()),
+        }
+    }
+
+    Ok(B2DO {
+        width: header.width as i32,
+        height: header.height as i32,
+        stride: header.width as i32,
+        bitmap: pixels,
+    })
+}
+
+pub(super) fn is_tga(bytes: &[u8]) -> bool {
+    match read_header(bytes) {
+        Ok(header) => header.color_map_type <= 1 && header.width > 0 && header.height > 0,
+        Err(_) => false,
+    }
+}
+
+fn read_header(bytes: &[u8]) -> Result<TgaHeader, TgaError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(TgaError::TruncatedPixelData {
+            expected: HEADER_SIZE,
+            actual: bytes.len(),
+        });
+    }
+
+    let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+
+    let header = TgaHeader {
+        id_length: bytes[0] as usize,
+        color_map_type: bytes[1],
+        image_type: bytes[2],
+        color_map_first: read_u16(3),
+        color_map_length: read_u16(5),
+        color_map_depth: bytes[7],
+        width: read_u16(12),
+        height: read_u16(14),
+        pixel_depth: bytes[16],
+        descriptor: bytes[17],
+    };
+
+    let allowed_pixel_depths: &[u8] = match header.image_type {
+        IMAGE_TYPE_COLOR_MAPPED | IMAGE_TYPE_RLE_COLOR_MAPPED => &[8, 16],
+        IMAGE_TYPE_TRUECOLOR | IMAGE_TYPE_RLE_TRUECOLOR => &[15, 16, 24, 32],
+        IMAGE_TYPE_GRAYSCALE | IMAGE_TYPE_RLE_GRAYSCALE => &[8, 16],
+        image_type => return Err(TgaError::UnsupportedImageType(image_type)),
+    };
+    if !allowed_pixel_depths.contains(&header.pixel_depth) {
+        return Err(TgaError::UnsupportedPixelDepth {
+            image_type: header.image_type,
+            pixel_depth: header.pixel_depth,
+        });
+    }
+
+    if header.color_map_type == 1 && !matches!(header.color_map_depth, 15 | 16 | 24 | 32) {
+        return Err(TgaError::UnsupportedColorMapDepth(header.color_map_depth));
+    }
+
+    Ok(header)
+}
+
+// Packets may cross scanlines, so the whole image is decoded as one stream
+// No more is reserved than the packets could expand to, a short body can't claim a huge buffer
+fn decode_rle(data: &[u8], expected: usize, bytes_per_pixel: usize) -> Result<Vec<u8>, TgaError> {
+    let truncated = |actual| TgaError::TruncatedPixelData { expected, actual };
+
+    let max_expansion = (RLE_COUNT_MASK as usize + 1) * bytes_per_pixel;
+    let mut pixel_data = Vec::with_capacity(expected.min(data.len().saturating_mul(max_expansion)));
+    let mut position = 0;
+    while pixel_data.len() < expected {
+        let packet = *data
+            .get(position)
+            .ok_or_else(|| truncated(pixel_data.len()))?;
+        position += 1;
+
+        let count = (packet & RLE_COUNT_MASK) as usize + 1;
+        let size = match packet & RLE_PACKET {
+            0 => count * bytes_per_pixel,
+            _ => bytes_per_pixel,
+        };
+        let packet_data = data
+            .get(position..position + size)
+            .ok_or_else(|| truncated(pixel_data.len()))?;
+        position += size;
+
+        if packet & RLE_PACKET == 0 {
+            pixel_data.extend_from_slice(packet_data);
+        } else {
+            for _ in 0..count {
+                pixel_data.extend_from_slice(packet_data);
+            }
+        }
+    }
+    pixel_data.truncate(expected);
+
+    Ok(pixel_data)
+}
+
+fn bytes_per_entry(depth: u8) -> usize {
+    (depth as usize).div_ceil(8)
+}
+
+fn truecolor(c: &[u8], depth: u8, has_alpha: bool, dither: Dither, (x, y): (i32, i32)) -> u16 {
+    match depth {
+        15 | 16 => {
+            let color = u16::from_le_bytes([c[0], c[1]]);
+            // The top bit is an attribute that marks opaque pixels
+            if depth == 16 && has_alpha && color & 0x8000 == 0 {
+                MASK_COLOR
+            } else {
+                color & 0x7FFF
+            }
+        }
+        24 => dither.color_from_rgb8(c[2], c[1], c[0], x, y),
+        _ => {
+            if has_alpha && c[3] < ALPHA_THRESHOLD {
+                MASK_COLOR
+            } else {
+                dither.color_from_rgb8(c[2], c[1], c[0], x, y)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::color_from_rgb8;
+
+    const RED: u16 = 31 << 10;
+    const GREEN: u16 = 31 << 5;
+    const BLUE: u16 = 31;
+    const WHITE: u16 = 0x7FFF;
+
+    // Red, green, blue and white in BGR
+    const COLOR_MAP: [u8; 12] = [0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255];
+
+    // Every file carries a short image id so the decoder has to skip it
+    fn tga(
+        (width, height): (u16, u16),
+        image_type: u8,
+        pixel_depth: u8,
+        descriptor: u8,
+        (color_map_depth, color_map): (u8, &[u8]),
+        data: &[u8],
+    ) -> Vec<u8> {
+        let id = b"id";
+        let mut bytes = vec![id.len() as u8, !color_map.is_empty() as u8, image_type];
+        bytes.extend(0u16.to_le_bytes());
+        let color_map_length = match color_map.len() {
+            0 => 0,
+            size => size / bytes_per_entry(color_map_depth),
+        };
+        bytes.extend((color_map_length as u16).to_le_bytes());
+        bytes.push(color_map_depth);
+        bytes.extend([0; 4]);
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend([pixel_depth, descriptor]);
+        bytes.extend(id);
+        bytes.extend(color_map);
+        bytes.extend(data);
+        bytes
+    }
+
+    fn load(bytes: &[u8]) -> Result<B2DO, TgaError> {
+        load_tga_from_bytes(bytes, Dither::None)
+    }
+
+    #[test]
+    fn loads_truecolor_in_every_orientation() {
+        let data = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+        let bytes = tga((2, 2), IMAGE_TYPE_TRUECOLOR, 24, 0, (0, &[]), &data);
+        let image = load(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.bitmap, [RED, WHITE, BLUE, GREEN]);
+
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM;
+        let bytes = tga(
+            (2, 2),
+            IMAGE_TYPE_TRUECOLOR,
+            24,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [BLUE, GREEN, RED, WHITE]);
+
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM | DESCRIPTOR_RIGHT_TO_LEFT;
+        let bytes = tga(
+            (2, 2),
+            IMAGE_TYPE_TRUECOLOR,
+            24,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [GREEN, BLUE, WHITE, RED]);
+    }
+
+    #[test]
+    fn loads_truecolor_alpha() {
+        let data = [0, 0, 255, 255, 255, 255, 255, 0];
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM | 8;
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_TRUECOLOR,
+            32,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, MASK_COLOR]);
+
+        // Without alpha bits in the descriptor the fourth byte is ignored
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM;
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_TRUECOLOR,
+            32,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, WHITE]);
+
+        let data = Vec::from_iter([0x8000 | RED, WHITE].into_iter().flat_map(u16::to_le_bytes));
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM | 1;
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_TRUECOLOR,
+            16,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, MASK_COLOR]);
+
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM;
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_TRUECOLOR,
+            15,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, WHITE]);
+    }
+
+    #[test]
+    fn loads_color_mapped_and_grayscale() {
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM;
+        let color_map = (24, &COLOR_MAP[..]);
+        let bytes = tga(
+            (4, 1),
+            IMAGE_TYPE_COLOR_MAPPED,
+            8,
+            descriptor,
+            color_map,
+            &[0, 1, 2, 3],
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [RED, GREEN, BLUE, WHITE]);
+
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_GRAYSCALE,
+            8,
+            descriptor,
+            (0, &[]),
+            &[0, 128],
+        );
+        assert_eq!(
+            load(&bytes).unwrap().bitmap,
+            [0, color_from_rgb8(128, 128, 128)]
+        );
+
+        let data = [255, 255, 255, 0];
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_GRAYSCALE,
+            16,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [WHITE, MASK_COLOR]);
+    }
+
+    #[test]
+    fn loads_rle() {
+        // The run packet crosses into the second scanline
+        let descriptor = DESCRIPTOR_TOP_TO_BOTTOM;
+        let data = [0x83, 0, 0, 255, 0x01, 0, 255, 0, 255, 0, 0];
+        let bytes = tga(
+            (3, 2),
+            IMAGE_TYPE_RLE_TRUECOLOR,
+            24,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(
+            load(&bytes).unwrap().bitmap,
+            [RED, RED, RED, RED, GREEN, BLUE]
+        );
+
+        let color_map = (24, &COLOR_MAP[..]);
+        let data = [0x81, 2, 0x01, 3, 0];
+        let bytes = tga(
+            (4, 1),
+            IMAGE_TYPE_RLE_COLOR_MAPPED,
+            8,
+            descriptor,
+            color_map,
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [BLUE, BLUE, WHITE, RED]);
+
+        let data = [0x81, 255];
+        let bytes = tga(
+            (2, 1),
+            IMAGE_TYPE_RLE_GRAYSCALE,
+            8,
+            descriptor,
+            (0, &[]),
+            &data,
+        );
+        assert_eq!(load(&bytes).unwrap().bitmap, [WHITE, WHITE]);
+    }
+
+    #[test]
+    fn rejects_broken_headers() {
+        let bytes = tga((1, 1), 4, 24, 0, (0, &[]), &[0; 3]);
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::UnsupportedImageType(4))
+        ));
+
+        let bytes = tga((1, 1), IMAGE_TYPE_TRUECOLOR, 8, 0, (0, &[]), &[0]);
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::UnsupportedPixelDepth {
+                image_type: IMAGE_TYPE_TRUECOLOR,
+                pixel_depth: 8
+            })
+        ));
+
+        let bytes = tga((1, 1), IMAGE_TYPE_COLOR_MAPPED, 8, 0, (8, &[0; 4]), &[0]);
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::UnsupportedColorMapDepth(8))
+        ));
+
+        let bytes = tga((1, 1), IMAGE_TYPE_COLOR_MAPPED, 8, 0, (0, &[]), &[0]);
+        assert!(matches!(load(&bytes), Err(TgaError::MissingColorMap)));
+
+        let bytes = tga(
+            (u16::MAX, u16::MAX),
+            IMAGE_TYPE_TRUECOLOR,
+            32,
+            0,
+            (0, &[]),
+            &[],
+        );
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::InvalidDimensions {
+                width: 65535,
+                height: 65535
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = tga((2, 1), IMAGE_TYPE_TRUECOLOR, 24, 0, (0, &[]), &[0; 5]);
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::TruncatedPixelData {
+                expected: 6,
+                actual: 5
+            })
+        ));
+
+        let bytes = tga(
+            (4, 1),
+            IMAGE_TYPE_RLE_TRUECOLOR,
+            24,
+            0,
+            (0, &[]),
+            &[0x81, 0, 0, 0, 0x81],
+        );
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::TruncatedPixelData {
+                expected: 12,
+                actual: 6
+            })
+        ));
+
+        let mut bytes = tga(
+            (1, 1),
+            IMAGE_TYPE_COLOR_MAPPED,
+            8,
+            0,
+            (24, &COLOR_MAP),
+            &[0],
+        );
+        bytes.truncate(HEADER_SIZE + 4);
+        assert!(matches!(
+            load(&bytes),
+            Err(TgaError::TruncatedPixelData { .. })
+        ));
+
+        assert!(matches!(
+            load(&[0; 10]),
+            Err(TgaError::TruncatedPixelData {
+                expected: HEADER_SIZE,
+                actual: 10
+            })
+        ));
+    }
+}
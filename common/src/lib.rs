@@ -1,7 +1,10 @@
+pub mod assets;
 pub mod buffer2d;
 pub mod console;
+pub mod font;
 pub mod image;
 pub mod math;
 pub mod platform;
 pub mod renderer;
 pub mod utils;
+pub mod wad;
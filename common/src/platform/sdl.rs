@@ -1,7 +1,10 @@
 use core::slice;
 use std::time::Instant;
 
-use crate::buffer2d::B2DS;
+use crate::buffer2d::{
+    pixel::{Pixel, PixelFormat},
+    B2DS,
+};
 
 use super::{
     input::{Input, InputCode},
@@ -43,7 +46,7 @@ pub fn sdl_main<A: Application>(mut app: A) -> Result<(), String> {
 
     let mut texture = texture_creator
         .create_texture_streaming(
-            PixelFormatEnum::ARGB1555,
+            sdl_pixel_format(u16::FORMAT),
             surface_width as u32,
             surface_height as u32,
         )
@@ -105,10 +108,12 @@ pub fn sdl_main<A: Application>(mut app: A) -> Result<(), String> {
         previous = current;
 
         // canvas.clear();
-        texture.with_lock(None, |buffer: &mut [u8], _pitch: usize| unsafe {
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| unsafe {
+            // Rows may be padded, so the pitch becomes the stride
+            let stride = (pitch / std::mem::size_of::<u16>()) as i32;
             let bitmap = slice::from_raw_parts_mut(
                 buffer.as_mut_ptr() as *mut u16,
-                (surface_height * surface_width) as usize,
+                (surface_height * stride) as usize,
             );
             app.main_loop(
                 &input,
@@ -116,6 +121,7 @@ pub fn sdl_main<A: Application>(mut app: A) -> Result<(), String> {
                 Some(B2DS {
                     width: surface_width,
                     height: surface_height,
+                    stride,
                     bitmap,
                 }),
             );
@@ -129,6 +135,16 @@ pub fn sdl_main<A: Application>(mut app: A) -> Result<(), String> {
     Ok(())
 }
 
+// The streaming texture takes the frame buffer as is, so the formats have to match exactly
+fn sdl_pixel_format(format: PixelFormat) -> PixelFormatEnum {
+    match format {
+        PixelFormat::Rgb555 => PixelFormatEnum::RGB555,
+        PixelFormat::Rgb565 => PixelFormatEnum::RGB565,
+        PixelFormat::Rgba8888 => PixelFormatEnum::RGBA8888,
+        PixelFormat::Indexed8 => PixelFormatEnum::Index8,
+    }
+}
+
 pub fn sdlkey_to_input_code(vkey: Keycode) -> InputCode {
     match vkey {
         Keycode::Backslash => InputCode::Back,
@@ -141,6 +157,7 @@ pub fn sdlkey_to_input_code(vkey: Keycode) -> InputCode {
         Keycode::LAlt => InputCode::LAlt,
         Keycode::RAlt => InputCode::RAlt,
         Keycode::Pause => InputCode::Pause,
+        Keycode::PrintScreen => InputCode::Snapshot,
         Keycode::Escape => InputCode::Escape,
         Keycode::Space => InputCode::Space,
         Keycode::PageUp => InputCode::PageUp,
@@ -134,6 +134,7 @@ pub unsafe fn init_application<A: Application>(mut app: A) {
                         Some(B2DS {
                             width: user_data.bitmap_info.bmiHeader.biWidth,
                             height: user_data.bitmap_info.bmiHeader.biHeight.abs(),
+                            stride: user_data.bitmap_info.bmiHeader.biWidth,
                             bitmap: slice::from_raw_parts_mut(
                                 user_data.pixels,
                                 (user_data.bitmap_info.bmiHeader.biWidth
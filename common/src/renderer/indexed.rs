@@ -0,0 +1,178 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    buffer2d::{
+        pixel::{DirectPixel, Indexed8},
+        MASK_COLOR,
+    },
+    wad::graphics::{Colormap, Palette, PALETTE_SIZE},
+};
+
+pub const LIGHT_LEVELS: usize = 32;
+// PLAYPAL repeats black here, editors use it for the holes in indexed graphics
+pub const TRANSPARENT_INDEX: u8 = 247;
+
+// PLAYPAL layout, the first palette is the normal one
+const DAMAGE_PALETTES: Range<usize> = 1..9;
+const PICKUP_PALETTES: Range<usize> = 9..13;
+const DAMAGE_TINT: [u8; 4] = [255, 0, 0, 255];
+const PICKUP_TINT: [u8; 4] = [215, 186, 69, 255];
+
+// Light levels added per unit of view distance
+const DEFAULT_DISTANCE_FADE: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PaletteEffect {
+    None,
+    // Strength is 0..1 and picks one of the fades
+    Damage(f32),
+    Pickup(f32),
+}
+
+// Remembers every color it has matched, textures only use a handful of them
+pub struct Quantizer {
+    palette: Palette,
+    cache: HashMap<u16, Indexed8>,
+}
+
+impl Quantizer {
+    pub fn new(palette: &Palette) -> Self {
+        Self {
+            palette: *palette,
+            cache: HashMap::new(),
+        }
+    }
+
+    // Only MASK_COLOR itself becomes a hole, opaque colors never match TRANSPARENT_INDEX
+    pub fn index(&mut self, color: u16) -> Indexed8 {
+        if color == MASK_COLOR {
+            return Indexed8(TRANSPARENT_INDEX);
+        }
+
+        let palette = &self.palette;
+        *self.cache.entry(color).or_insert_with(|| {
+            let [r, g, b, _] = color.to_rgba8();
+            let distance = |entry: &u16| {
+                let [er, eg, eb, _] = entry.to_rgba8();
+                let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+                channel(r, er) + channel(g, eg) + channel(b, eb)
+            };
+
+            let index = palette
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != TRANSPARENT_INDEX as usize)
+                .min_by_key(|(_, entry)| distance(entry))
+                .map_or(0, |(index, _)| index);
+            Indexed8(index as u8)
+        })
+    }
+}
+
+pub struct IndexedPalette {
+    pub palettes: Vec<Palette>,
+    // Level 0 is full brightness, LIGHT_LEVELS - 1 the darkest
+    pub colormaps: Vec<Colormap>,
+    pub distance_fade: f32,
+    active: usize,
+    quantizer: Quantizer,
+}
+
+impl IndexedPalette {
+    // Missing fades and light levels are built from the first palette
+    pub fn new(mut palettes: Vec<Palette>, mut colormaps: Vec<Colormap>) -> Option<Self> {
+        let base = *palettes.first()?;
+
+        if palettes.len() < PICKUP_PALETTES.end {
+            palettes.truncate(1);
+            for (range, tint) in [
+                (DAMAGE_PALETTES, DAMAGE_TINT),
+                (PICKUP_PALETTES, PICKUP_TINT),
+            ] {
+                let steps = range.len() + 1;
+                for step in 1..steps {
+                    palettes.push(tint_palette(&base, tint, step as f32 / steps as f32));
+                }
+            }
+        }
+
+        if colormaps.len() < LIGHT_LEVELS {
+            colormaps = build_colormaps(&base);
+        }
+        colormaps.truncate(LIGHT_LEVELS);
+
+        Some(Self {
+            palettes,
+            colormaps,
+            distance_fade: DEFAULT_DISTANCE_FADE,
+            active: 0,
+            quantizer: Quantizer::new(&base),
+        })
+    }
+
+    pub fn base(&self) -> &Palette {
+        &self.palettes[0]
+    }
+
+    pub fn active(&self) -> &Palette {
+        &self.palettes[self.active]
+    }
+
+    pub fn set_effect(&mut self, effect: PaletteEffect) {
+        self.active = match effect {
+            PaletteEffect::None => 0,
+            PaletteEffect::Damage(strength) => effect_palette(DAMAGE_PALETTES, strength),
+            PaletteEffect::Pickup(strength) => effect_palette(PICKUP_PALETTES, strength),
+        };
+    }
+
+    // Light is 0..1 like the vertex colors, distance pushes towards the darker maps
+    pub fn light_level(&self, light: f32, distance: f32) -> usize {
+        let level = (1.0 - light) * LIGHT_LEVELS as f32 + distance * self.distance_fade;
+        (level.max(0.0) as usize).min(LIGHT_LEVELS - 1)
+    }
+
+    pub fn shade(&self, index: Indexed8, level: usize) -> Indexed8 {
+        Indexed8(self.colormaps[level][index.0 as usize])
+    }
+
+    pub fn index(&mut self, color: u16) -> Indexed8 {
+        self.quantizer.index(color)
+    }
+}
+
+fn effect_palette(range: Range<usize>, strength: f32) -> usize {
+    match strength > 0.0 {
+        true => {
+            let step = (strength.min(1.0) * range.len() as f32).ceil() as usize;
+            range.start + step.clamp(1, range.len()) - 1
+        }
+        false => 0,
+    }
+}
+
+fn tint_palette(palette: &Palette, tint: [u8; 4], amount: f32) -> Palette {
+    palette.map(|color| {
+        let rgba = color.to_rgba8();
+        let channel =
+            |i: usize| (rgba[i] as f32 + (tint[i] as f32 - rgba[i] as f32) * amount) as u8;
+        u16::from_rgba8([channel(0), channel(1), channel(2), 255])
+    })
+}
+
+// Every level darkens the palette a bit further and matches it back onto itself
+fn build_colormaps(palette: &Palette) -> Vec<Colormap> {
+    let mut quantizer = Quantizer::new(palette);
+    Vec::from_iter((0..LIGHT_LEVELS).map(|level| {
+        let brightness = 1.0 - level as f32 / LIGHT_LEVELS as f32;
+        let mut colormap = [0; PALETTE_SIZE];
+        for (entry, color) in colormap.iter_mut().zip(palette) {
+            let [r, g, b, _] = color.to_rgba8();
+            let darken = |channel: u8| (channel as f32 * brightness) as u8;
+            *entry = quantizer
+                .index(u16::from_rgba8([darken(r), darken(g), darken(b), 255]))
+                .0;
+        }
+        colormap
+    }))
+}
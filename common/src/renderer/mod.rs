@@ -1,5 +1,6 @@
 pub mod camera;
 mod clipping;
+pub mod indexed;
 pub mod utils;
 
 use std::{cell::RefCell, fmt::Display, rc::Rc};
@@ -9,19 +10,44 @@ use cgmath::{
 };
 
 use crate::{
-    buffer2d::{B2D, B2DO, B2DT},
+    assets::{AssetManager, TextureHandle},
+    buffer2d::{
+        blend::{blend, BlendMode},
+        dither::Dither,
+        mip_chain::MipChain,
+        pixel::Indexed8,
+        sampler::Sampler,
+        B2DO,
+    },
     math::{max3, min3, orient2d},
-    utils::{calculate_index, color_from_vec},
+    utils::{calculate_index, color_from_tuple, color_from_vec},
 };
 
-use self::clipping::{clip_line_to_frustum, clip_triangle_to_frustum};
+use self::{
+    clipping::{clip_line_to_frustum, clip_triangle_to_frustum},
+    indexed::{IndexedPalette, TRANSPARENT_INDEX},
+};
 
 pub enum RenderDebugMode {
     None,
     ZBuffer,
     Clickables,
+    MipLevels,
 }
 
+// Level 0 keeps its colors, every smaller level gets its own tint
+const MIP_LEVEL_TINTS: [u16; 6] = [
+    color_from_tuple((31, 31, 31)),
+    color_from_tuple((31, 8, 8)),
+    color_from_tuple((31, 31, 8)),
+    color_from_tuple((8, 31, 8)),
+    color_from_tuple((8, 31, 31)),
+    color_from_tuple((8, 8, 31)),
+];
+
+// Geometry this far away is white in the z-buffer debug view
+const DEPTH_DEBUG_RANGE: f32 = 16.0;
+
 pub struct Renderer {
     vertex_storage: VertexStorage,
     view_mat: Matrix4<f32>,
@@ -31,8 +57,14 @@ pub struct Renderer {
 
     z_buffer: Vec<f32>,
     pub color_buffer: Rc<RefCell<B2DO>>,
+    // Rect is (x, y, width, height) inside the color buffer
+    region: (i32, i32, i32, i32),
+    // Only used while indexed is set, end() resolves it into the color buffer
+    index_buffer: B2DO<Indexed8>,
 
     pub debug_mode: RenderDebugMode,
+    pub dither: Dither,
+    pub indexed: Option<IndexedPalette>,
     pub stat_tris: u32,
     pub stat_sprites: u32,
 }
@@ -60,8 +92,12 @@ impl Renderer {
             ),
             z_buffer: vec![0.0; (height * width) as usize],
             color_buffer: color_buffer.clone(),
+            region: (0, 0, width, height),
+            index_buffer: B2DO::new(0, 0),
 
             debug_mode: RenderDebugMode::None,
+            dither: Dither::None,
+            indexed: None,
             stat_tris: 0,
             stat_sprites: 0,
         }
@@ -71,12 +107,55 @@ impl Renderer {
         self.view_mat = view_mat;
         self.proj_mat = proj_mat;
         self.view_proj_mat = proj_mat * view_mat;
-        self.color_buffer.borrow_mut().bitmap.fill(7500);
+        let (x, y, width, height) = self.region;
+        self.color_buffer
+            .borrow_mut()
+            .sub_view(x, y, width, height)
+            .fill(7500);
+        if let Some(indexed) = &mut self.indexed {
+            self.index_buffer.resize(width, height);
+            self.index_buffer.fill(indexed.index(7500));
+        }
         self.z_buffer.fill(f32::MAX);
         self.stat_tris = 0;
         self.stat_sprites = 0;
     }
 
+    // Indexed frames are drawn with the active palette, so palette effects cost nothing
+    pub fn end(&mut self) {
+        let Some(indexed) = &self.indexed else {
+            return;
+        };
+
+        let palette = indexed.active();
+        let (x, y, width, height) = self.region;
+        let mut color_buffer = self.color_buffer.borrow_mut();
+        let mut view = color_buffer.sub_view(x, y, width, height);
+        for y in 0..view.height.min(self.index_buffer.height) {
+            for x in 0..view.width.min(self.index_buffer.width) {
+                let index = self.index_buffer.get_color(x as usize, y as usize);
+                view.set_color(x, y, index.resolve(palette));
+            }
+        }
+    }
+
+    // Renders into part of the color buffer only, e.g. for split-screen or insets
+    // The rect is clamped to the whole color buffer, so regions can be moved and grown freely
+    pub fn set_region(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        let (x, y, width, height) = {
+            let color_buffer = self.color_buffer.borrow();
+            let x0 = x.clamp(0, color_buffer.width);
+            let y0 = y.clamp(0, color_buffer.height);
+            let x1 = (x + width).clamp(x0, color_buffer.width);
+            let y1 = (y + height).clamp(y0, color_buffer.height);
+            (x0, y0, x1 - x0, y1 - y0)
+        };
+
+        self.region = (x, y, width, height);
+        self.z_buffer.resize((width * height) as usize, f32::MAX);
+        self.set_viewport(Vector4::new(0.0, 0.0, width as f32, height as f32));
+    }
+
     pub fn set_viewport(&mut self, viewport: Vector4<f32>) {
         self.viewport = Vector4::new(
             viewport.z / 2.0,
@@ -86,6 +165,12 @@ impl Renderer {
         );
     }
 
+    // Depths are z / w, the debug view shows them as view distance up to DEPTH_DEBUG_RANGE
+    fn debug_depth(&self, depth: f32) -> f32 {
+        let distance = self.proj_mat.w.z / (depth + self.proj_mat.z.z);
+        (distance / DEPTH_DEBUG_RANGE).clamp(0.0, 1.0)
+    }
+
     fn perspective_division(&self, mut pos: &mut Vector4<f32>) {
         let inv_w = 1.0 / pos.w;
         pos.x *= inv_w;
@@ -110,14 +195,39 @@ impl Renderer {
         self.perspective_division(&mut v.pos);
         self.transform_viewport(&mut v.pos);
 
-        self.color_buffer.borrow_mut().set_color(
-            v.pos.x as i32,
-            v.pos.y as i32,
-            color_from_vec(v.color),
-        )
+        let color = color_from_vec(v.color);
+        match &mut self.indexed {
+            Some(indexed) => {
+                let index = indexed.index(color);
+                self.index_buffer
+                    .set_color(v.pos.x as i32, v.pos.y as i32, index)
+            }
+            None => {
+                let (x, y, width, height) = self.region;
+                self.color_buffer
+                    .borrow_mut()
+                    .sub_view(x, y, width, height)
+                    .set_color(v.pos.x as i32, v.pos.y as i32, color)
+            }
+        }
+    }
+
+    pub fn draw_line(&mut self, p0: Vector4<f32>, p1: Vector4<f32>, c: u16) {
+        self.draw_line_impl(p0, p1, c, false);
+    }
+
+    // Hidden behind whatever geometry was already drawn this frame
+    pub fn draw_line_depth_tested(&mut self, p0: Vector4<f32>, p1: Vector4<f32>, c: u16) {
+        self.draw_line_impl(p0, p1, c, true);
     }
 
-    pub fn draw_line(&mut self, mut p0: Vector4<f32>, mut p1: Vector4<f32>, c: u16) {
+    fn draw_line_impl(
+        &mut self,
+        mut p0: Vector4<f32>,
+        mut p1: Vector4<f32>,
+        c: u16,
+        depth_test: bool,
+    ) {
         p0 = self.view_proj_mat * p0;
         p1 = self.view_proj_mat * p1;
 
@@ -128,19 +238,40 @@ impl Renderer {
             self.transform_viewport(&mut p0);
             self.transform_viewport(&mut p1);
 
-            self.color_buffer
-                .borrow_mut()
-                .draw_line_2d(p0.truncate(), p1.truncate(), c);
+            let (p0, p1) = (p0.truncate(), p1.truncate());
+            if let Some(indexed) = &mut self.indexed {
+                let c = indexed.index(c);
+                match depth_test {
+                    true => self
+                        .index_buffer
+                        .draw_line_2d_depth(p0, p1, c, &self.z_buffer),
+                    false => self.index_buffer.draw_line_2d(p0, p1, c),
+                }
+                return;
+            }
+
+            let (x, y, width, height) = self.region;
+            let mut color_buffer = self.color_buffer.borrow_mut();
+            let mut view = color_buffer.sub_view(x, y, width, height);
+            match depth_test {
+                true => view.draw_line_2d_depth(p0, p1, c, &self.z_buffer),
+                false => view.draw_line_2d(p0, p1, c),
+            }
         }
     }
 
-    pub fn draw_triangle<'t, T: B2DT>(
+    pub fn draw_triangle(
         &mut self,
         v0w: &Vertex,
         v1w: &Vertex,
         v2w: &Vertex,
-        texture: Option<&'t B2D<T>>,
+        texture: Option<TextureHandle>,
+        sampler: Sampler,
+        assets: &AssetManager,
     ) {
+        let indexed_texture = texture.and_then(|texture| assets.indexed_mip_chain(texture));
+        let texture = texture.map(|texture| assets.mip_chain(texture));
+
         self.vertex_storage.vertices.clear();
 
         self.vertex_storage.vertices.push(*v0w);
@@ -166,17 +297,21 @@ impl Renderer {
                     self.vertex_storage.vertices[self.vertex_storage.indices[t + 1]],
                     self.vertex_storage.vertices[self.vertex_storage.indices[t + 2]],
                     texture,
+                    indexed_texture,
+                    sampler,
                 );
             }
         }
     }
 
-    fn rasterize_triangle<'t, T: B2DT>(
+    fn rasterize_triangle(
         &mut self,
         v0: Vertex,
         v1: Vertex,
         v2: Vertex,
-        texture: Option<&'t B2D<T>>,
+        texture: Option<&MipChain>,
+        indexed_texture: Option<&MipChain<Indexed8>>,
+        sampler: Sampler,
     ) {
         let vertices: [Vertex; 3] = [v0, v1, v2];
         let mut pos_viewport = [v0.pos, v1.pos, v2.pos];
@@ -222,7 +357,41 @@ impl Renderer {
         let mut bc_screen_y_row = orient2d(pos_screen[2], pos_screen[0], min_x, min_y);
         let mut bc_screen_z_row = orient2d(pos_screen[0], pos_screen[1], min_x, min_y);
 
+        // UVs are (sum of uv * bc / w) / (sum of bc / w), both sums step linearly on screen
+        let inv_w = pos_viewport.map(|pos| 1.0 / pos.w);
+        let step_x = [a12, a20, a01].map(|a| a as f32);
+        let step_y = [b12, b20, b01].map(|b| b as f32);
+        let inv_w_dx = (0..3).map(|i| step_x[i] * inv_w[i]).sum::<f32>();
+        let inv_w_dy = (0..3).map(|i| step_y[i] * inv_w[i]).sum::<f32>();
+        let uv_w_dx = (0..3)
+            .map(|i| vertices[i].uv * step_x[i] * inv_w[i])
+            .sum::<Vector2<f32>>();
+        let uv_w_dy = (0..3)
+            .map(|i| vertices[i].uv * step_y[i] * inv_w[i])
+            .sum::<Vector2<f32>>();
+
+        // The barycentric sum is twice the area everywhere, so 1 / w is bc_sum / area
+        let area = (bc_screen_x_row + bc_screen_y_row + bc_screen_z_row) as f32;
+        // z / w is affine on screen, so it is interpolated without perspective correction
+        let depth = Vector3::new(
+            pos_viewport[0].z * inv_w[0],
+            pos_viewport[1].z * inv_w[1],
+            pos_viewport[2].z * inv_w[2],
+        ) / area;
+        let (flat, white) = match &mut self.indexed {
+            Some(indexed) => (
+                indexed.index(color_from_vec(
+                    (vertices[0].color + vertices[1].color + vertices[2].color) / 3.0,
+                )),
+                indexed.index(color_from_tuple((31, 31, 31))),
+            ),
+            None => (Indexed8(0), Indexed8(0)),
+        };
+
+        let (region_x, region_y, region_width, region_height) = self.region;
         let mut color_buffer = self.color_buffer.borrow_mut();
+        let mut color_buffer =
+            color_buffer.sub_view(region_x, region_y, region_width, region_height);
 
         for y in min_y..max_y {
             let mut bc_screen_x = bc_screen_x_row;
@@ -234,42 +403,108 @@ impl Renderer {
                     let index = calculate_index(x, y, color_buffer.width);
 
                     let mut bc_clip = Vector3::new(
-                        bc_screen_x as f32 / pos_viewport[0].w,
-                        bc_screen_y as f32 / pos_viewport[1].w,
-                        bc_screen_z as f32 / pos_viewport[2].w,
+                        bc_screen_x as f32 * inv_w[0],
+                        bc_screen_y as f32 * inv_w[1],
+                        bc_screen_z as f32 * inv_w[2],
                     );
-                    bc_clip = bc_clip / (bc_clip.x + bc_clip.y + bc_clip.z);
+                    let bc_sum = bc_clip.x + bc_clip.y + bc_clip.z;
+                    bc_clip /= bc_sum;
 
                     let frag_depth =
-                        Vector3::new(vertices[0].pos.z, vertices[1].pos.z, vertices[2].pos.z)
-                            .dot(bc_clip);
+                        Vector3::new(bc_screen_x as f32, bc_screen_y as f32, bc_screen_z as f32)
+                            .dot(depth);
 
                     if frag_depth < self.z_buffer[index] {
                         self.z_buffer[index] = frag_depth;
 
-                        let color = match self.debug_mode {
-                            RenderDebugMode::None => match texture {
-                                Some(ref texture) => {
-                                    let uv = Matrix3::from_cols(
-                                        vertices[0].uv.extend(0.0),
-                                        vertices[1].uv.extend(0.0),
-                                        vertices[2].uv.extend(0.0),
-                                    ) * bc_clip;
-                                    texture.sample(uv.x, uv.y)
-                                }
-                                None => color_from_vec(
-                                    Matrix3::from_cols(
-                                        vertices[0].color,
-                                        vertices[1].color,
-                                        vertices[2].color,
-                                    ) * bc_clip,
-                                ),
-                            },
-                            RenderDebugMode::ZBuffer => z_to_color(frag_depth),
-                            RenderDebugMode::Clickables => todo!(),
+                        let uv = Matrix3::from_cols(
+                            vertices[0].uv.extend(0.0),
+                            vertices[1].uv.extend(0.0),
+                            vertices[2].uv.extend(0.0),
+                        ) * bc_clip;
+                        let lod = |levels: usize, width: i32, height: i32| match levels {
+                            1 => 0.0,
+                            _ => {
+                                let size = Vector2::new(width as f32, height as f32);
+                                let uv = uv.truncate();
+                                let dx = (uv_w_dx - uv * inv_w_dx) / bc_sum;
+                                let dy = (uv_w_dy - uv * inv_w_dy) / bc_sum;
+                                texture_lod(dx, dy, size)
+                            }
                         };
-
-                        color_buffer.set_color_by_index(index, color);
+                        let vertex_color = Matrix3::from_cols(
+                            vertices[0].color,
+                            vertices[1].color,
+                            vertices[2].color,
+                        ) * bc_clip;
+
+                        match &self.indexed {
+                            Some(indexed) => {
+                                let index = match (&self.debug_mode, indexed_texture) {
+                                    (RenderDebugMode::ZBuffer, _) => indexed.shade(
+                                        white,
+                                        indexed.light_level(self.debug_depth(frag_depth), 0.0),
+                                    ),
+                                    (_, Some(texture)) => {
+                                        let base = texture.base();
+                                        let (_, mip) = texture.level(lod(
+                                            texture.levels.len(),
+                                            base.width,
+                                            base.height,
+                                        ));
+                                        let light =
+                                            (vertex_color.x + vertex_color.y + vertex_color.z)
+                                                / 3.0;
+                                        let level = indexed.light_level(light, area / bc_sum);
+                                        indexed
+                                            .shade(sampler.sample_nearest(mip, uv.x, uv.y), level)
+                                    }
+                                    // Vertex colors are matched once, only the distance varies
+                                    (_, None) => {
+                                        indexed.shade(flat, indexed.light_level(1.0, area / bc_sum))
+                                    }
+                                };
+                                self.index_buffer.set_color(x, y, index);
+                            }
+                            None => {
+                                let color = match self.debug_mode {
+                                    RenderDebugMode::None | RenderDebugMode::MipLevels => {
+                                        match texture {
+                                            Some(texture) => {
+                                                let base = texture.base();
+                                                let (level, mip) = texture.level(lod(
+                                                    texture.levels.len(),
+                                                    base.width,
+                                                    base.height,
+                                                ));
+                                                let color = mip.sample(uv.x, uv.y, sampler);
+
+                                                match self.debug_mode {
+                                                    RenderDebugMode::MipLevels => blend(
+                                                        color,
+                                                        color,
+                                                        BlendMode::Tint(mip_level_tint(level)),
+                                                    ),
+                                                    _ => color,
+                                                }
+                                            }
+                                            // Keyed on the color buffer position so regions share one pattern
+                                            None => self.dither.color_from_vec(
+                                                vertex_color,
+                                                x + region_x,
+                                                y + region_y,
+                                            ),
+                                        }
+                                    }
+                                    RenderDebugMode::ZBuffer => {
+                                        z_to_color(self.debug_depth(frag_depth))
+                                    }
+                                    RenderDebugMode::Clickables => todo!(),
+                                };
+
+                                color_buffer.set_color(x, y, color);
+                            }
+                        }
                     }
                 }
 
@@ -286,7 +521,17 @@ impl Renderer {
         self.stat_tris += 1;
     }
 
-    pub fn draw_sprite(&mut self, mut pos_bottom: Vector4<f32>, size: f32, sprite: &B2DO) {
+    pub fn draw_sprite(
+        &mut self,
+        mut pos_bottom: Vector4<f32>,
+        size: f32,
+        sprite: TextureHandle,
+        sampler: Sampler,
+        assets: &AssetManager,
+    ) {
+        let indexed_sprite = assets.indexed_mip_chain(sprite);
+        let sprite = assets.mip_chain(sprite);
+
         let mut pos_top = pos_bottom;
 
         pos_bottom = self.view_proj_mat * pos_bottom;
@@ -300,21 +545,26 @@ impl Renderer {
             * self.view_mat)
             * pos_top;
 
-        let bottom_z = pos_bottom.z;
-        let top_z = pos_top.z;
-
+        // Depth is z / w like everything else in the z-buffer
         self.perspective_division(&mut pos_bottom);
         self.transform_viewport(&mut pos_bottom);
 
         self.perspective_division(&mut pos_top);
         self.transform_viewport(&mut pos_top);
 
+        let bottom_z = pos_bottom.z;
+        let top_z = pos_top.z;
+
         let size = (pos_top.y - pos_bottom.y).abs() as i32;
 
         let offset_x = pos_bottom.x as i32 - (size / 2);
         let offset_y = pos_bottom.y as i32 - size;
 
+        // Screen positions are relative to the region, like the z and index buffers
+        let (region_x, region_y, region_width, region_height) = self.region;
         let mut color_buffer = self.color_buffer.borrow_mut();
+        let mut color_buffer =
+            color_buffer.sub_view(region_x, region_y, region_width, region_height);
 
         let start_x = offset_x.max(0);
         let end_x = offset_x + size;
@@ -343,6 +593,17 @@ impl Renderer {
 
         let uv_step = 1.0 / size as f32;
 
+        // Sprites are square on screen, so one texel footprint covers the whole quad
+        let lod = (sprite.base().height as f32 * uv_step).log2();
+        let (level, sprite) = sprite.level(lod);
+        let tint = mip_level_tint(level);
+        let indexed_sprite = indexed_sprite.map(|sprite| sprite.level(lod).1);
+        // Sprites carry no sector light, they only fade with distance
+        let light_level = self
+            .indexed
+            .as_ref()
+            .map_or(0, |indexed| indexed.light_level(1.0, pos_bottom.w));
+
         let mut frag_depth = top_z;
         let frag_depth_step = (bottom_z - top_z) * uv_step;
 
@@ -352,7 +613,21 @@ impl Renderer {
             for dest_x in start_x..end_x {
                 u += uv_step;
 
-                let color = sprite.sample(u, v);
+                if let (Some(indexed), Some(sprite)) = (&self.indexed, indexed_sprite) {
+                    let texel = sampler.sample_nearest(sprite, u, v);
+                    let index = calculate_index(dest_x, dest_y, color_buffer.width);
+                    if texel.0 != TRANSPARENT_INDEX && frag_depth < self.z_buffer[index] {
+                        self.z_buffer[index] = frag_depth;
+                        self.index_buffer.set_color(
+                            dest_x,
+                            dest_y,
+                            indexed.shade(texel, light_level),
+                        );
+                    }
+                    continue;
+                }
+
+                let color = sprite.sample(u, v, sampler);
 
                 if color == crate::buffer2d::MASK_COLOR {
                     continue;
@@ -363,12 +638,16 @@ impl Renderer {
                 if frag_depth < self.z_buffer[index] {
                     self.z_buffer[index] = frag_depth;
 
-                    color_buffer.set_color_by_index(
-                        index,
+                    color_buffer.set_color(
+                        dest_x,
+                        dest_y,
                         match self.debug_mode {
                             RenderDebugMode::None => color,
-                            RenderDebugMode::ZBuffer => z_to_color(frag_depth),
+                            RenderDebugMode::ZBuffer => z_to_color(self.debug_depth(frag_depth)),
                             RenderDebugMode::Clickables => todo!(),
+                            RenderDebugMode::MipLevels => {
+                                blend(color, color, BlendMode::Tint(tint))
+                            }
                         },
                     )
                 }
@@ -418,6 +697,17 @@ pub struct VertexStorage {
     pub indices_out: Vec<usize>,
 }
 
+fn mip_level_tint(level: usize) -> u16 {
+    MIP_LEVEL_TINTS[level.min(MIP_LEVEL_TINTS.len() - 1)]
+}
+
+// UV derivatives are per screen pixel, the level is log2 of the larger footprint in texels
+fn texture_lod(dx: Vector2<f32>, dy: Vector2<f32>, size: Vector2<f32>) -> f32 {
+    let dx = Vector2::new(dx.x * size.x, dx.y * size.y);
+    let dy = Vector2::new(dy.x * size.x, dy.y * size.y);
+    0.5 * dx.magnitude2().max(dy.magnitude2()).log2()
+}
+
 fn z_to_color(z: f32) -> u16 {
     let channel = z.clamp(0.0, 1.0);
     color_from_vec(Vector3::new(channel, channel, channel))
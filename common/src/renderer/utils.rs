@@ -9,7 +9,7 @@ const GRID_COLOR: u16 = color_from_tuple((31, 31, 31));
 
 pub fn draw_grid(renderer: &mut Renderer, origin_reference: Vector3<f32>, cell_size: f32) {
     for i in -GRID_SIZE..GRID_SIZE {
-        renderer.draw_line(
+        renderer.draw_line_depth_tested(
             Vector3::new(i as f32 * cell_size, 0.0, -GRID_SIZE as f32 * cell_size).extend(1.0),
             Vector3::new(
                 i as f32 * cell_size,
@@ -20,7 +20,7 @@ pub fn draw_grid(renderer: &mut Renderer, origin_reference: Vector3<f32>, cell_s
             GRID_COLOR,
         );
 
-        renderer.draw_line(
+        renderer.draw_line_depth_tested(
             Vector3::new(-GRID_SIZE as f32 * cell_size, 0.0, i as f32 * cell_size).extend(1.0),
             Vector3::new(
                 (GRID_SIZE - 1) as f32 * cell_size,
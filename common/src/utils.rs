@@ -33,23 +33,17 @@ pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
 }
 
 pub fn read_str_4bytes(buf: &[u8], offset: usize) -> String {
-    String::from_utf8(
-        buf[offset..offset + 4]
-            .try_into()
-            .expect("slice with incorrect length"),
-    )
-    .expect("")
-    .replace('\0', "")
+    read_str(&buf[offset..offset + 4])
 }
 
 pub fn read_str_8bytes(buf: &[u8], offset: usize) -> String {
-    String::from_utf8(
-        buf[offset..offset + 8]
-            .try_into()
-            .expect("slice with incorrect length"),
-    )
-    .expect("")
-    .replace('\0', "")
+    read_str(&buf[offset..offset + 8])
+}
+
+// Names are NUL padded and may carry garbage after the terminator
+fn read_str(buf: &[u8]) -> String {
+    let end = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
 }
 
 // Rect is (x, y, width, height)
@@ -72,6 +66,15 @@ pub fn color_from_vec(color: Vector3<f32>) -> u16 {
     ))
 }
 
+// Channels above 31 saturate instead of spilling into their neighbors
 pub const fn color_from_tuple(color: (u16, u16, u16)) -> u16 {
-    (color.0 << 10) + (color.1 << 5) + color.2
+    (saturate_channel(color.0) << 10) | (saturate_channel(color.1) << 5) | saturate_channel(color.2)
+}
+
+const fn saturate_channel(channel: u16) -> u16 {
+    if channel > 31 {
+        31
+    } else {
+        channel
+    }
 }
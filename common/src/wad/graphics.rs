@@ -0,0 +1,328 @@
+use std::io::{Read, Seek};
+
+use crate::{
+    buffer2d::{B2DO, MASK_COLOR},
+    image::{checked_pixel_count, color_from_rgb8},
+    utils::{read_i16, read_u16, read_u32},
+};
+
+use super::{Wad, WadError};
+
+pub const PALETTE_SIZE: usize = 256;
+pub const FLAT_SIZE: i32 = 64;
+
+const PLAYPAL_ENTRY_SIZE: usize = PALETTE_SIZE * 3;
+const PATCH_HEADER_SIZE: usize = 8;
+const POST_END: u8 = 0xFF;
+// Top delta, length and the two padding bytes
+const POST_MIN_SIZE: usize = 4;
+const POST_MAX_LENGTH: usize = 255;
+const POST_MAX_TOP_DELTA: usize = 254;
+
+pub type Palette = [u16; PALETTE_SIZE];
+pub type Colormap = [u8; PALETTE_SIZE];
+
+// Every PLAYPAL entry is a full palette, the first one is used for normal rendering
+pub fn decode_playpal(data: &[u8]) -> Result<Vec<Palette>, WadError> {
+    if data.is_empty() || !data.len().is_multiple_of(PLAYPAL_ENTRY_SIZE) {
+        return Err(WadError::InvalidLumpSize {
+            kind: "PLAYPAL",
+            size: data.len(),
+        });
+    }
+
+    Ok(Vec::from_iter(data.chunks_exact(PLAYPAL_ENTRY_SIZE).map(
+        |entry| {
+            let mut palette = [0; PALETTE_SIZE];
+            for (color, c) in palette.iter_mut().zip(entry.chunks_exact(3)) {
+                *color = color_from_rgb8(c[0], c[1], c[2]);
+                // Opaque pixels must never turn into holes
+                if *color == MASK_COLOR {
+                    *color -= 1;
+                }
+            }
+            palette
+        },
+    )))
+}
+
+pub fn decode_colormap(data: &[u8]) -> Result<Vec<Colormap>, WadError> {
+    if data.len() < PALETTE_SIZE {
+        return Err(WadError::InvalidLumpSize {
+            kind: "COLORMAP",
+            size: data.len(),
+        });
+    }
+
+    Ok(Vec::from_iter(data.chunks_exact(PALETTE_SIZE).map(|map| {
+        let mut colormap = [0; PALETTE_SIZE];
+        colormap.copy_from_slice(map);
+        colormap
+    })))
+}
+
+// Flats are raw 64 pixel wide rows, some ports ship taller ones
+pub fn decode_flat(data: &[u8], palette: &Palette) -> Result<B2DO, WadError> {
+    let width = FLAT_SIZE as usize;
+    if data.len() < width * width || !data.len().is_multiple_of(width) {
+        return Err(WadError::InvalidLumpSize {
+            kind: "flat",
+            size: data.len(),
+        });
+    }
+
+    Ok(B2DO {
+        width: FLAT_SIZE,
+        height: (data.len() / width) as i32,
+        stride: FLAT_SIZE,
+        bitmap: Vec::from_iter(data.iter().map(|index| palette[*index as usize])),
+    })
+}
+
+// Patches are stored as columns of posts, everything between posts stays transparent
+pub fn decode_patch(data: &[u8], palette: &Palette) -> Result<B2DO, WadError> {
+    if data.len() < PATCH_HEADER_SIZE {
+        return Err(WadError::InvalidLumpSize {
+            kind: "patch",
+            size: data.len(),
+        });
+    }
+
+    let width = read_u16(data, 0) as usize;
+    let height = read_u16(data, 2) as usize;
+    if data.len() < PATCH_HEADER_SIZE + width * 4 {
+        return Err(WadError::InvalidPatch);
+    }
+
+    // Every post moves down a limited amount, so the lump size bounds the rows posts can reach
+    let max_posts = (data.len() - PATCH_HEADER_SIZE) / POST_MIN_SIZE;
+    let reachable = max_posts * POST_MAX_TOP_DELTA + POST_MAX_LENGTH;
+    let pixel_count = checked_pixel_count(width, height)
+        .filter(|_| height <= reachable)
+        .ok_or(WadError::InvalidPatch)?;
+
+    let mut bitmap = vec![MASK_COLOR; pixel_count];
+    for x in 0..width {
+        let mut position = read_u32(data, PATCH_HEADER_SIZE + x * 4) as usize;
+        let mut top = -1;
+        loop {
+            let top_delta = *data.get(position).ok_or(WadError::InvalidPatch)?;
+            if top_delta == POST_END {
+                break;
+            }
+
+            // Tall patches encode posts past 254 relative to the previous one
+            top = match top_delta as i32 {
+                top_delta if top_delta <= top => top + top_delta,
+                top_delta => top_delta,
+            };
+
+            let length = *data.get(position + 1).ok_or(WadError::InvalidPatch)? as usize;
+            // Posts are wrapped in one unused padding byte on each side
+            let post = data
+                .get(position + 3..position + 3 + length)
+                .ok_or(WadError::InvalidPatch)?;
+
+            for (i, index) in post.iter().enumerate() {
+                let y = top as usize + i;
+                if y < height {
+                    bitmap[y * width + x] = palette[*index as usize];
+                }
+            }
+
+            position += length + 4;
+        }
+    }
+
+    Ok(B2DO {
+        width: width as i32,
+        height: height as i32,
+        stride: width as i32,
+        bitmap,
+    })
+}
+
+// Left and top offsets position sprites relative to their origin
+pub fn patch_offsets(data: &[u8]) -> Result<(i16, i16), WadError> {
+    if data.len() < PATCH_HEADER_SIZE {
+        return Err(WadError::InvalidLumpSize {
+            kind: "patch",
+            size: data.len(),
+        });
+    }
+
+    Ok((read_i16(data, 4), read_i16(data, 6)))
+}
+
+impl<R: Read + Seek> Wad<R> {
+    pub fn read_playpal(&mut self) -> Result<Vec<Palette>, WadError> {
+        decode_playpal(&self.read_lump_by_name("PLAYPAL")?)
+    }
+
+    pub fn read_colormap(&mut self) -> Result<Vec<Colormap>, WadError> {
+        decode_colormap(&self.read_lump_by_name("COLORMAP")?)
+    }
+
+    pub fn read_flat(&mut self, name: &str, palette: &Palette) -> Result<B2DO, WadError> {
+        let index = self
+            .find_in_markers(name, &[("F_START", "F_END"), ("FF_START", "FF_END")])
+            .ok_or_else(|| WadError::LumpNotFound(name.to_string()))?;
+        decode_flat(&self.read_lump(index)?, palette)
+    }
+
+    pub fn read_sprite(&mut self, name: &str, palette: &Palette) -> Result<B2DO, WadError> {
+        let index = self
+            .find_in_markers(name, &[("S_START", "S_END"), ("SS_START", "SS_END")])
+            .ok_or_else(|| WadError::LumpNotFound(name.to_string()))?;
+        decode_patch(&self.read_lump(index)?, palette)
+    }
+
+    pub fn read_patch(&mut self, name: &str, palette: &Palette) -> Result<B2DO, WadError> {
+        decode_patch(&self.read_lump_by_name(name)?, palette)
+    }
+
+    // Flat and sprite names may clash with other lumps, so their namespaces are searched first
+    fn find_in_markers(&self, name: &str, markers: &[(&str, &str)]) -> Option<usize> {
+        markers
+            .iter()
+            .rev()
+            .filter_map(|(start, end)| self.marker_range(start, end))
+            .find_map(|range| self.find_lump_in(name, range))
+            .or_else(|| self.find_lump(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every index maps to itself, so decoded pixels show which palette entry they came from
+    fn palette() -> Palette {
+        let mut palette = [0; PALETTE_SIZE];
+        for (i, color) in palette.iter_mut().enumerate() {
+            *color = i as u16;
+        }
+        palette
+    }
+
+    // Each column is a list of (top delta, pixels) posts
+    fn patch((width, height): (u16, u16), columns: &[&[(u8, &[u8])]]) -> Vec<u8> {
+        let mut bytes = width.to_le_bytes().to_vec();
+        bytes.extend(height.to_le_bytes());
+        bytes.extend((-5i16).to_le_bytes());
+        bytes.extend(10i16.to_le_bytes());
+
+        let mut offset = PATCH_HEADER_SIZE + columns.len() * 4;
+        let mut posts = vec![];
+        for column in columns {
+            bytes.extend((offset as u32).to_le_bytes());
+            let start = posts.len();
+            for (top_delta, pixels) in *column {
+                posts.extend([*top_delta, pixels.len() as u8, 0]);
+                posts.extend(*pixels);
+                posts.push(0);
+            }
+            posts.push(POST_END);
+            offset += posts.len() - start;
+        }
+        bytes.extend(posts);
+        bytes
+    }
+
+    #[test]
+    fn decodes_patch_posts() {
+        let bytes = patch((2, 4), &[&[(0, &[1, 2]), (3, &[3])], &[]]);
+        let image = decode_patch(&bytes, &palette()).unwrap();
+        assert_eq!((image.width, image.height), (2, 4));
+        assert_eq!(
+            image.bitmap,
+            [1, MASK_COLOR, 2, MASK_COLOR, MASK_COLOR, MASK_COLOR, 3, MASK_COLOR]
+        );
+        assert_eq!(patch_offsets(&bytes).unwrap(), (-5, 10));
+    }
+
+    #[test]
+    fn decodes_tall_patches() {
+        // The second post starts 10 rows below the first and runs past the bottom
+        let bytes = patch((1, 300), &[&[(254, &[7]), (10, &[8; 40])]]);
+        let image = decode_patch(&bytes, &palette()).unwrap();
+        assert_eq!(image.bitmap[253], MASK_COLOR);
+        assert_eq!(image.bitmap[254], 7);
+        assert!(image.bitmap[255..264]
+            .iter()
+            .all(|color| *color == MASK_COLOR));
+        assert!(image.bitmap[264..].iter().all(|color| *color == 8));
+    }
+
+    #[test]
+    fn rejects_broken_patches() {
+        assert!(matches!(
+            decode_patch(&[1, 0, 1, 0], &palette()),
+            Err(WadError::InvalidLumpSize { kind: "patch", .. })
+        ));
+        assert!(patch_offsets(&[0; 4]).is_err());
+
+        let mut bytes = patch((1, 4), &[&[(0, &[1, 2])]]);
+        bytes[0] = 4;
+        assert!(matches!(
+            decode_patch(&bytes, &palette()),
+            Err(WadError::InvalidPatch)
+        ));
+
+        let mut bytes = patch((1, 4), &[&[(0, &[1, 2])]]);
+        bytes[PATCH_HEADER_SIZE] = 100;
+        assert!(matches!(
+            decode_patch(&bytes, &palette()),
+            Err(WadError::InvalidPatch)
+        ));
+
+        let mut bytes = patch((1, 4), &[&[(0, &[1, 2])]]);
+        bytes.truncate(bytes.len() - 3);
+        assert!(matches!(
+            decode_patch(&bytes, &palette()),
+            Err(WadError::InvalidPatch)
+        ));
+
+        // No post in a lump this small can reach that far down
+        let bytes = patch((1, u16::MAX), &[&[(0, &[1])]]);
+        assert!(matches!(
+            decode_patch(&bytes, &palette()),
+            Err(WadError::InvalidPatch)
+        ));
+    }
+
+    #[test]
+    fn keeps_palette_colors_opaque() {
+        let mut data = vec![0; PLAYPAL_ENTRY_SIZE * 2];
+        data[..6].copy_from_slice(&[255, 0, 0, 0, 255, 255]);
+        let palettes = decode_playpal(&data).unwrap();
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0][0], 31 << 10);
+        assert_eq!(palettes[0][1], MASK_COLOR - 1);
+        assert_eq!(palettes[1][0], 0);
+
+        assert!(decode_playpal(&[]).is_err());
+        assert!(decode_playpal(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn decodes_colormaps_and_flats() {
+        let colormaps = decode_colormap(&[3; PALETTE_SIZE * 2]).unwrap();
+        assert_eq!(colormaps.len(), 2);
+        assert_eq!(colormaps[1][0], 3);
+        assert!(decode_colormap(&[0; 100]).is_err());
+
+        let flat = decode_flat(&[5; 64 * 64], &palette()).unwrap();
+        assert_eq!((flat.width, flat.height), (64, 64));
+        assert!(flat.bitmap.iter().all(|color| *color == 5));
+
+        let flat = decode_flat(&[5; 64 * 128], &palette()).unwrap();
+        assert_eq!(flat.height, 128);
+
+        assert!(matches!(
+            decode_flat(&[5; 64 * 64 + 1], &palette()),
+            Err(WadError::InvalidLumpSize { kind: "flat", .. })
+        ));
+        assert!(decode_flat(&[5; 64], &palette()).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::io::{Read, Seek};
+
+use crate::utils::{read_i16, read_str_8bytes, read_u16};
+
+use super::{Wad, WadError};
+
+pub const NO_SIDEDEF: u16 = 0xFFFF;
+pub const NO_TEXTURE: &str = "-";
+pub const SKY_FLAT: &str = "F_SKY1";
+
+// Map lumps follow their marker in a fixed order, BLOCKMAP being the last one
+const MAP_LUMP_COUNT: usize = 10;
+
+const VERTEX_SIZE: usize = 4;
+const LINEDEF_SIZE: usize = 14;
+const SIDEDEF_SIZE: usize = 30;
+const SECTOR_SIZE: usize = 26;
+const THING_SIZE: usize = 10;
+
+#[derive(Debug, Copy, Clone)]
+pub struct MapVertex {
+    pub x: i16,
+    pub y: i16,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Linedef {
+    pub v1: u16,
+    pub v2: u16,
+    pub flags: u16,
+    pub special: u16,
+    pub tag: u16,
+    pub front: u16,
+    pub back: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sidedef {
+    pub x_offset: i16,
+    pub y_offset: i16,
+    pub upper: String,
+    pub lower: String,
+    pub middle: String,
+    pub sector: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sector {
+    pub floor_height: i16,
+    pub ceiling_height: i16,
+    pub floor_flat: String,
+    pub ceiling_flat: String,
+    pub light_level: i16,
+    pub special: u16,
+    pub tag: u16,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Thing {
+    pub x: i16,
+    pub y: i16,
+    pub angle: u16,
+    pub kind: u16,
+    pub flags: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Map {
+    pub vertexes: Vec<MapVertex>,
+    pub linedefs: Vec<Linedef>,
+    pub sidedefs: Vec<Sidedef>,
+    pub sectors: Vec<Sector>,
+    pub things: Vec<Thing>,
+}
+
+impl Map {
+    pub fn from_lumps(
+        vertexes: &[u8],
+        linedefs: &[u8],
+        sidedefs: &[u8],
+        sectors: &[u8],
+        things: &[u8],
+    ) -> Result<Self, WadError> {
+        let map = Self {
+            vertexes: decode_entries(vertexes, "VERTEXES", VERTEX_SIZE, |entry| MapVertex {
+                x: read_i16(entry, 0),
+                y: read_i16(entry, 2),
+            })?,
+            linedefs: decode_entries(linedefs, "LINEDEFS", LINEDEF_SIZE, |entry| Linedef {
+                v1: read_u16(entry, 0),
+                v2: read_u16(entry, 2),
+                flags: read_u16(entry, 4),
+                special: read_u16(entry, 6),
+                tag: read_u16(entry, 8),
+                front: read_u16(entry, 10),
+                back: read_u16(entry, 12),
+            })?,
+            sidedefs: decode_entries(sidedefs, "SIDEDEFS", SIDEDEF_SIZE, |entry| Sidedef {
+                x_offset: read_i16(entry, 0),
+                y_offset: read_i16(entry, 2),
+                upper: read_str_8bytes(entry, 4).to_ascii_uppercase(),
+                lower: read_str_8bytes(entry, 12).to_ascii_uppercase(),
+                middle: read_str_8bytes(entry, 20).to_ascii_uppercase(),
+                sector: read_u16(entry, 28),
+            })?,
+            sectors: decode_entries(sectors, "SECTORS", SECTOR_SIZE, |entry| Sector {
+                floor_height: read_i16(entry, 0),
+                ceiling_height: read_i16(entry, 2),
+                floor_flat: read_str_8bytes(entry, 4).to_ascii_uppercase(),
+                ceiling_flat: read_str_8bytes(entry, 12).to_ascii_uppercase(),
+                light_level: read_i16(entry, 20),
+                special: read_u16(entry, 22),
+                tag: read_u16(entry, 24),
+            })?,
+            things: decode_entries(things, "THINGS", THING_SIZE, |entry| Thing {
+                x: read_i16(entry, 0),
+                y: read_i16(entry, 2),
+                angle: read_u16(entry, 4),
+                kind: read_u16(entry, 6),
+                flags: read_u16(entry, 8),
+            })?,
+        };
+
+        map.validate()?;
+
+        Ok(map)
+    }
+
+    pub fn front_sidedef(&self, linedef: &Linedef) -> Option<&Sidedef> {
+        self.sidedefs.get(linedef.front as usize)
+    }
+
+    pub fn back_sidedef(&self, linedef: &Linedef) -> Option<&Sidedef> {
+        match linedef.back {
+            NO_SIDEDEF => None,
+            back => self.sidedefs.get(back as usize),
+        }
+    }
+
+    // Geometry building indexes freely, so broken references are rejected up front
+    fn validate(&self) -> Result<(), WadError> {
+        for (index, linedef) in self.linedefs.iter().enumerate() {
+            let invalid_vertex = linedef.v1 as usize >= self.vertexes.len()
+                || linedef.v2 as usize >= self.vertexes.len();
+            let invalid_front = linedef.front as usize >= self.sidedefs.len();
+            let invalid_back =
+                linedef.back != NO_SIDEDEF && linedef.back as usize >= self.sidedefs.len();
+            if invalid_vertex || invalid_front || invalid_back {
+                return Err(WadError::InvalidMapReference {
+                    kind: "LINEDEFS",
+                    index,
+                });
+            }
+        }
+
+        for (index, sidedef) in self.sidedefs.iter().enumerate() {
+            if sidedef.sector as usize >= self.sectors.len() {
+                return Err(WadError::InvalidMapReference {
+                    kind: "SIDEDEFS",
+                    index,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_entries<T>(
+    data: &[u8],
+    kind: &'static str,
+    entry_size: usize,
+    decode: impl Fn(&[u8]) -> T,
+) -> Result<Vec<T>, WadError> {
+    if !data.len().is_multiple_of(entry_size) {
+        return Err(WadError::InvalidLumpSize {
+            kind,
+            size: data.len(),
+        });
+    }
+
+    Ok(Vec::from_iter(data.chunks_exact(entry_size).map(decode)))
+}
+
+impl<R: Read + Seek> Wad<R> {
+    // Map names such as E1M1 or MAP01 are markers, the lumps after them belong to that map
+    pub fn read_map(&mut self, name: &str) -> Result<Map, WadError> {
+        let marker = self
+            .find_lump(name)
+            .ok_or_else(|| WadError::LumpNotFound(name.to_string()))?;
+        let range = marker + 1..(marker + 1 + MAP_LUMP_COUNT).min(self.lumps.len());
+
+        let mut read = |lump: &str| match self.find_lump_in(lump, range.clone()) {
+            Some(index) => self.read_lump(index),
+            None => Err(WadError::LumpNotFound(format!("{}/{}", name, lump))),
+        };
+
+        let things = read("THINGS")?;
+        let linedefs = read("LINEDEFS")?;
+        let sidedefs = read("SIDEDEFS")?;
+        let vertexes = read("VERTEXES")?;
+        let sectors = read("SECTORS")?;
+
+        Map::from_lumps(&vertexes, &linedefs, &sidedefs, &sectors, &things)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> [u8; 8] {
+        let mut padded = [0; 8];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        padded
+    }
+
+    fn vertex(x: i16, y: i16) -> Vec<u8> {
+        [x.to_le_bytes(), y.to_le_bytes()].concat()
+    }
+
+    fn linedef(v1: u16, v2: u16, front: u16, back: u16) -> Vec<u8> {
+        Vec::from_iter(
+            [v1, v2, 1, 0, 0, front, back]
+                .into_iter()
+                .flat_map(u16::to_le_bytes),
+        )
+    }
+
+    fn sidedef(middle: &str, sector: u16) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        bytes.extend(name(NO_TEXTURE));
+        bytes.extend(name(NO_TEXTURE));
+        bytes.extend(name(middle));
+        bytes.extend(sector.to_le_bytes());
+        bytes
+    }
+
+    fn sector(floor_height: i16, ceiling_height: i16, floor: &str, ceiling: &str) -> Vec<u8> {
+        let mut bytes = [floor_height.to_le_bytes(), ceiling_height.to_le_bytes()].concat();
+        bytes.extend(name(floor));
+        bytes.extend(name(ceiling));
+        bytes.extend(160i16.to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes
+    }
+
+    fn thing(x: i16, y: i16, angle: u16, kind: u16) -> Vec<u8> {
+        let mut bytes = vertex(x, y);
+        bytes.extend([angle, kind, 7].into_iter().flat_map(u16::to_le_bytes));
+        bytes
+    }
+
+    // Two vertexes joined by a one sided line
+    fn from_lumps(linedefs: &[u8], sidedefs: &[u8]) -> Result<Map, WadError> {
+        let vertexes = [vertex(0, 0), vertex(64, -32)].concat();
+        let sectors = sector(0, 128, "floor4_8", SKY_FLAT);
+        Map::from_lumps(
+            &vertexes,
+            linedefs,
+            sidedefs,
+            &sectors,
+            &thing(32, 16, 90, 1),
+        )
+    }
+
+    #[test]
+    fn decodes_map_lumps() {
+        let map = from_lumps(&linedef(0, 1, 0, NO_SIDEDEF), &sidedef("startan3", 0)).unwrap();
+        assert_eq!((map.vertexes[1].x, map.vertexes[1].y), (64, -32));
+
+        let linedef = map.linedefs[0];
+        assert_eq!((linedef.v1, linedef.v2, linedef.flags), (0, 1, 1));
+        let front = map.front_sidedef(&linedef).unwrap();
+        assert_eq!(front.middle, "STARTAN3");
+        assert_eq!(front.upper, NO_TEXTURE);
+        assert!(map.back_sidedef(&linedef).is_none());
+
+        let sector = &map.sectors[0];
+        assert_eq!((sector.floor_height, sector.ceiling_height), (0, 128));
+        assert_eq!(sector.floor_flat, "FLOOR4_8");
+        assert_eq!(sector.ceiling_flat, SKY_FLAT);
+        assert_eq!(sector.light_level, 160);
+
+        let thing = map.things[0];
+        assert_eq!((thing.x, thing.y, thing.angle, thing.kind), (32, 16, 90, 1));
+        assert_eq!(thing.flags, 7);
+    }
+
+    #[test]
+    fn rejects_bad_linedef_references() {
+        let sidedefs = sidedef("startan3", 0);
+        let bad_linedefs = [
+            linedef(0, 2, 0, NO_SIDEDEF),
+            linedef(0, 1, 1, NO_SIDEDEF),
+            linedef(0, 1, 0, 1),
+        ];
+        for bad in bad_linedefs {
+            let linedefs = [linedef(1, 0, 0, NO_SIDEDEF), bad].concat();
+            assert!(matches!(
+                from_lumps(&linedefs, &sidedefs),
+                Err(WadError::InvalidMapReference {
+                    kind: "LINEDEFS",
+                    index: 1
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_sidedef_references() {
+        let sidedefs = [sidedef("startan3", 0), sidedef("startan3", 1)].concat();
+        assert!(matches!(
+            from_lumps(&linedef(0, 1, 0, 1), &sidedefs),
+            Err(WadError::InvalidMapReference {
+                kind: "SIDEDEFS",
+                index: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_uneven_lumps() {
+        let linedefs = linedef(0, 1, 0, NO_SIDEDEF);
+        assert!(matches!(
+            from_lumps(&linedefs[..13], &sidedef("startan3", 0)),
+            Err(WadError::InvalidLumpSize {
+                kind: "LINEDEFS",
+                size: 13
+            })
+        ));
+    }
+}
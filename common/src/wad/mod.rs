@@ -0,0 +1,287 @@
+pub mod graphics;
+pub mod map;
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use crate::utils::{read_str_4bytes, read_str_8bytes, read_u32};
+
+const HEADER_SIZE: usize = 12;
+const DIRECTORY_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WadKind {
+    Iwad,
+    Pwad,
+}
+
+#[derive(Debug)]
+pub enum WadError {
+    Io(io::Error),
+    InvalidIdentification(String),
+    TruncatedDirectory,
+    LumpNotFound(String),
+    TruncatedLump(String),
+    InvalidLumpSize { kind: &'static str, size: usize },
+    InvalidPatch,
+    InvalidMapReference { kind: &'static str, index: usize },
+}
+
+impl Display for WadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WadError::Io(error) => write!(f, "[WAD] IO error: {}", error),
+            WadError::InvalidIdentification(identification) => write!(
+                f,
+                "[WAD] Identification \"{}\" is neither IWAD nor PWAD!",
+                identification
+            ),
+            WadError::TruncatedDirectory => write!(f, "[WAD] Lump directory is truncated!"),
+            WadError::LumpNotFound(name) => write!(f, "[WAD] Lump \"{}\" not found!", name),
+            WadError::TruncatedLump(name) => write!(f, "[WAD] Lump \"{}\" is truncated!", name),
+            WadError::InvalidLumpSize { kind, size } => {
+                write!(f, "[WAD] {} lump has invalid size {}!", kind, size)
+            }
+            WadError::InvalidPatch => write!(f, "[WAD] Patch column data is corrupt!"),
+            WadError::InvalidMapReference { kind, index } => write!(
+                f,
+                "[WAD] {} entry {} references missing map data!",
+                kind, index
+            ),
+        }
+    }
+}
+
+impl Error for WadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WadError {
+    fn from(error: io::Error) -> Self {
+        WadError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lump {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+pub struct Wad<R: Read + Seek> {
+    reader: R,
+    pub kind: WadKind,
+    pub lumps: Vec<Lump>,
+}
+
+impl Wad<BufReader<File>> {
+    pub fn open(path: &str) -> Result<Self, WadError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Wad<R> {
+    pub fn from_reader(mut reader: R) -> Result<Self, WadError> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        let kind = match read_str_4bytes(&header, 0).as_str() {
+            "IWAD" => WadKind::Iwad,
+            "PWAD" => WadKind::Pwad,
+            identification => {
+                return Err(WadError::InvalidIdentification(identification.to_string()))
+            }
+        };
+        let lump_count = read_u32(&header, 4) as usize;
+        let directory_offset = read_u32(&header, 8) as u64;
+
+        let mut directory = vec![];
+        reader.seek(SeekFrom::Start(directory_offset))?;
+        reader
+            .by_ref()
+            .take((lump_count * DIRECTORY_ENTRY_SIZE) as u64)
+            .read_to_end(&mut directory)?;
+        if directory.len() != lump_count * DIRECTORY_ENTRY_SIZE {
+            return Err(WadError::TruncatedDirectory);
+        }
+
+        let lumps =
+            Vec::from_iter(
+                directory
+                    .chunks_exact(DIRECTORY_ENTRY_SIZE)
+                    .map(|entry| Lump {
+                        offset: read_u32(entry, 0),
+                        size: read_u32(entry, 4),
+                        name: read_str_8bytes(entry, 8).to_ascii_uppercase(),
+                    }),
+            );
+
+        Ok(Self {
+            reader,
+            kind,
+            lumps,
+        })
+    }
+
+    // Later lumps override earlier ones, the same way PWADs replace IWAD content
+    pub fn find_lump(&self, name: &str) -> Option<usize> {
+        self.lumps
+            .iter()
+            .rposition(|lump| lump.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn find_lump_after(&self, name: &str, start: usize) -> Option<usize> {
+        self.lumps
+            .iter()
+            .skip(start)
+            .position(|lump| lump.name.eq_ignore_ascii_case(name))
+            .map(|index| index + start)
+    }
+
+    pub fn find_lump_in(&self, name: &str, range: Range<usize>) -> Option<usize> {
+        let start = range.start;
+        self.lumps[range]
+            .iter()
+            .rposition(|lump| lump.name.eq_ignore_ascii_case(name))
+            .map(|index| index + start)
+    }
+
+    // Lumps strictly between markers such as F_START and F_END
+    pub fn marker_range(&self, start_marker: &str, end_marker: &str) -> Option<Range<usize>> {
+        let start = self
+            .lumps
+            .iter()
+            .position(|lump| lump.name.eq_ignore_ascii_case(start_marker))?;
+        let end = self.find_lump_after(end_marker, start + 1)?;
+        Some(start + 1..end)
+    }
+
+    pub fn read_lump(&mut self, index: usize) -> Result<Vec<u8>, WadError> {
+        let lump = &self.lumps[index];
+
+        let mut data = vec![];
+        self.reader.seek(SeekFrom::Start(lump.offset as u64))?;
+        self.reader
+            .by_ref()
+            .take(lump.size as u64)
+            .read_to_end(&mut data)?;
+        if data.len() != lump.size as usize {
+            return Err(WadError::TruncatedLump(lump.name.clone()));
+        }
+
+        Ok(data)
+    }
+
+    pub fn read_lump_by_name(&mut self, name: &str) -> Result<Vec<u8>, WadError> {
+        match self.find_lump(name) {
+            Some(index) => self.read_lump(index),
+            None => Err(WadError::LumpNotFound(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // Lump data follows the header, the directory comes last like in the original tools
+    fn wad(identification: &[u8; 4], lumps: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = identification.to_vec();
+        bytes.extend([0; 8]);
+
+        let mut directory = vec![];
+        for (name, data) in lumps {
+            directory.extend((bytes.len() as u32).to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            let mut padded = [0; 8];
+            padded[..name.len()].copy_from_slice(name.as_bytes());
+            directory.extend(padded);
+            bytes.extend(*data);
+        }
+
+        let directory_offset = bytes.len() as u32;
+        bytes[4..8].copy_from_slice(&(lumps.len() as u32).to_le_bytes());
+        bytes[8..12].copy_from_slice(&directory_offset.to_le_bytes());
+        bytes.extend(directory);
+        bytes
+    }
+
+    fn open(bytes: Vec<u8>) -> Result<Wad<Cursor<Vec<u8>>>, WadError> {
+        Wad::from_reader(Cursor::new(bytes))
+    }
+
+    #[test]
+    fn parses_the_directory() {
+        let lumps: [(&str, &[u8]); 5] = [
+            ("PLAYPAL", &[1, 2, 3]),
+            ("f_start", &[]),
+            ("FLOOR1", &[4, 5]),
+            ("F_END", &[]),
+            ("PLAYPAL", &[6]),
+        ];
+        let wad = open(wad(b"PWAD", &lumps)).unwrap();
+        assert_eq!(wad.kind, WadKind::Pwad);
+        assert_eq!(
+            Vec::from_iter(wad.lumps.iter().map(|lump| lump.name.as_str())),
+            ["PLAYPAL", "F_START", "FLOOR1", "F_END", "PLAYPAL"]
+        );
+        assert_eq!((wad.lumps[2].offset, wad.lumps[2].size), (15, 2));
+
+        assert_eq!(wad.find_lump("playpal"), Some(4));
+        assert_eq!(wad.find_lump("MISSING"), None);
+        assert_eq!(wad.find_lump_after("PLAYPAL", 1), Some(4));
+        assert_eq!(wad.find_lump_in("PLAYPAL", 0..3), Some(0));
+        assert_eq!(wad.marker_range("F_START", "F_END"), Some(2..3));
+        assert_eq!(wad.marker_range("S_START", "S_END"), None);
+    }
+
+    #[test]
+    fn reads_lumps() {
+        let lumps: [(&str, &[u8]); 2] = [("PLAYPAL", &[1, 2, 3]), ("PLAYPAL", &[6])];
+        let mut wad = open(wad(b"IWAD", &lumps)).unwrap();
+        assert_eq!(wad.kind, WadKind::Iwad);
+        assert_eq!(wad.read_lump(0).unwrap(), [1, 2, 3]);
+        assert_eq!(wad.read_lump_by_name("playpal").unwrap(), [6]);
+        assert!(matches!(
+            wad.read_lump_by_name("COLORMAP"),
+            Err(WadError::LumpNotFound(name)) if name == "COLORMAP"
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert!(matches!(
+            open(wad(b"JUNK", &[])),
+            Err(WadError::InvalidIdentification(identification)) if identification == "JUNK"
+        ));
+
+        assert!(matches!(open(b"IWAD".to_vec()), Err(WadError::Io(_))));
+
+        let mut bytes = wad(b"IWAD", &[("PLAYPAL", &[1, 2, 3])]);
+        bytes[4] = 2;
+        assert!(matches!(open(bytes), Err(WadError::TruncatedDirectory)));
+
+        // The directory claims more data than the file holds
+        let mut bytes = wad(b"IWAD", &[("PLAYPAL", &[1, 2, 3])]);
+        let size = bytes.len() - 12;
+        bytes[size] = 200;
+        let mut wad = open(bytes).unwrap();
+        assert!(matches!(
+            wad.read_lump(0),
+            Err(WadError::TruncatedLump(name)) if name == "PLAYPAL"
+        ));
+    }
+}